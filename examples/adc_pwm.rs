@@ -24,7 +24,7 @@ fn main() -> ! {
     let mut adc_pin = gpioa.pa1.into_analog();
     let mut adc = dp.ADC.adc(&mut rcc);
 
-    let mut pwm = dp.TIM4.pwm(gpiob.pb7, 1.khz(), &mut rcc);
+    let mut pwm = dp.TIM4.pwm(gpiob.pb7, 1.khz(), &mut rcc).unwrap();
     let max_duty = pwm.get_max_duty() / 4095;
     pwm.enable();
 
@@ -0,0 +1,32 @@
+#![deny(warnings)]
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_semihosting;
+extern crate stm32l1xx_hal as hal;
+
+use embedded_hal::digital::v2::ToggleableOutputPin;
+use hal::prelude::*;
+use hal::rcc::Config;
+use hal::stm32;
+use rt::entry;
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+
+    let rcc = dp.RCC.freeze(Config::default());
+    // TIM6 keeps SysTick free for an RTOS tick
+    let mut delay = dp.TIM6.delay(&rcc);
+
+    let gpiob = dp.GPIOB.split();
+    let mut led = gpiob.pb6.into_push_pull_output();
+
+    loop {
+        led.toggle().unwrap();
+        delay.delay_ms(300_u32);
+    }
+}
@@ -0,0 +1,63 @@
+//! # Runtime clock scaling example
+//!
+//! Alternates between 32 MHz from the PLL and 2.097 MHz from the MSI. After
+//! each switch USART2, the TIM2 timer and the SysTick delay are updated for
+//! the new clocks, so the baud rate and the timings stay the same.
+//!
+//! Hardware connections:
+//!   PA2 — USART2 TX
+//!   PA3 — USART2 RX
+
+#![deny(warnings)]
+#![no_main]
+#![no_std]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_semihosting;
+extern crate stm32l1xx_hal as hal;
+
+use core::fmt::Write;
+use hal::prelude::*;
+use hal::rcc::{Config, MSIRange, PLLSource};
+use hal::serial::{self, SerialExt};
+use hal::stm32;
+use nb::block;
+use rt::entry;
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let cp = cortex_m::Peripherals::take().unwrap();
+
+    let fast = Config::sysclk(32.mhz(), PLLSource::HSI).unwrap();
+    let slow = Config::msi(MSIRange::Range5);
+    let mut rcc = dp.RCC.freeze(fast);
+
+    let gpioa = dp.GPIOA.split();
+    let mut serial = dp
+        .USART2
+        .usart(
+            (gpioa.pa2, gpioa.pa3),
+            serial::Config::default().baudrate(9_600_u32.bps()),
+            &mut rcc,
+        )
+        .unwrap();
+    let mut timer = dp.TIM2.timer(1.hz(), &mut rcc);
+    let mut delay = cp.SYST.delay(rcc.clocks);
+
+    let mut idle = false;
+    loop {
+        idle = !idle;
+        let clocks = rcc.set_config(if idle { slow } else { fast }).unwrap();
+        serial.set_clocks(&clocks);
+        timer.set_clocks(&clocks).unwrap();
+        delay.set_clocks(&clocks);
+
+        writeln!(serial, "Running at {} Hz\r", clocks.sys_clk().0).unwrap();
+        for _ in 0..5 {
+            block!(timer.wait()).unwrap();
+        }
+        delay.delay_ms(500_u16);
+    }
+}
@@ -0,0 +1,64 @@
+//! # Clock security system example
+//!
+//! Runs at 32 MHz from the PLL fed by an 8 MHz HSE crystal, with the CSS
+//! watching the HSE. When the crystal fails the NMI handler falls back to the
+//! HSI and the main loop reconfigures USART2 for the new clocks.
+//!
+//! Hardware connections:
+//!   PA2 — USART2 TX
+//!   PA3 — USART2 RX
+
+#![deny(warnings)]
+#![no_main]
+#![no_std]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_semihosting;
+extern crate stm32l1xx_hal as hal;
+
+use core::fmt::Write;
+use hal::prelude::*;
+use hal::rcc::{self, Config, CssFallback, PLLSource};
+use hal::serial::{self, SerialExt};
+use hal::stm32;
+use rt::{entry, exception};
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+
+    let config = Config::sysclk(32.mhz(), PLLSource::HSE(8.mhz())).unwrap();
+    let mut rcc = dp.RCC.freeze(config);
+    rcc.enable_css();
+
+    let gpioa = dp.GPIOA.split();
+    let mut serial = dp
+        .USART2
+        .usart((gpioa.pa2, gpioa.pa3), serial_config(), &mut rcc)
+        .unwrap();
+
+    writeln!(
+        serial,
+        "Running from HSE at {} Hz\r",
+        rcc.clocks.sys_clk().0
+    )
+    .unwrap();
+
+    loop {
+        if let Some(clocks) = rcc.css_recovered() {
+            serial.reconfigure(serial_config(), &rcc);
+            writeln!(serial, "HSE failed, now at {} Hz\r", clocks.sys_clk().0).unwrap();
+        }
+        cortex_m::asm::delay(1_000_000);
+    }
+}
+
+fn serial_config() -> serial::Config {
+    serial::Config::default().baudrate(115_200_u32.bps())
+}
+
+#[exception]
+unsafe fn NonMaskableInt() {
+    rcc::css_nmi_handler(CssFallback::HSI);
+}
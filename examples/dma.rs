@@ -21,9 +21,15 @@ fn main() -> ! {
     let mut rcc = dp.RCC.freeze(Config::hsi());
 
     let dma1 = dp.DMA1.dma(&mut rcc);
-    let dma2 = dp.DMA2.dma(&mut rcc);
+    hprintln!("DMA1: {:?}", dma1);
+
+    // DMA2 only exists on category 3 and higher parts
+    #[cfg(feature = "_cat3plus")]
+    {
+        let dma2 = dp.DMA2.dma(&mut rcc);
+        hprintln!("DMA2: {:?}", dma2);
+    }
 
-    hprintln!("DMA1: {:?}, DMA2: {:?}", dma1, dma2);
     loop {
         cortex_m::asm::wfi();
     }
@@ -0,0 +1,36 @@
+#![deny(warnings)]
+#![no_main]
+#![no_std]
+
+extern crate panic_semihosting;
+extern crate stm32l1xx_hal as hal;
+
+use embassy_executor::Spawner;
+use embassy_time::Timer;
+use embedded_hal::digital::v2::ToggleableOutputPin;
+use hal::gpio::gpiob::PB6;
+use hal::gpio::{Output, PushPull};
+use hal::prelude::*;
+use hal::rcc::Config;
+use hal::stm32;
+
+#[embassy_executor::task]
+async fn blink(mut led: PB6<Output<PushPull>>) {
+    loop {
+        led.toggle().unwrap();
+        Timer::after_millis(500).await;
+    }
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    let dp = stm32::Peripherals::take().unwrap();
+    let mut rcc = dp.RCC.freeze(Config::default());
+
+    // TIM2 ticks at the 32768 Hz selected on embassy-time
+    hal::time_driver::init(dp.TIM2, &mut rcc).unwrap();
+
+    let gpiob = dp.GPIOB.split();
+    let led = gpiob.pb6.into_push_pull_output();
+    spawner.spawn(blink(led)).unwrap();
+}
@@ -0,0 +1,52 @@
+#![deny(warnings)]
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_semihosting;
+extern crate stm32l1xx_hal as hal;
+
+use embedded_hal::digital::v2::ToggleableOutputPin;
+use hal::delay::StopDelay;
+use hal::prelude::*;
+use hal::pwr::StopModeConfig;
+use hal::rcc::Config;
+use hal::rtc::Rtc;
+use hal::stm32;
+use rt::entry;
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let mut cp = cortex_m::Peripherals::take().unwrap();
+
+    let mut rcc = dp.RCC.freeze(Config::hsi());
+    let mut pwr = dp.PWR;
+    let mut rtc = Rtc::new(dp.RTC, &mut pwr);
+    let mut pwr = pwr.constrain();
+
+    let gpiob = dp.GPIOB.split();
+    let mut led = gpiob.pb6.into_push_pull_output();
+
+    // short waits in SLEEP mode on TIM6
+    let mut delay = dp.TIM6.delay(&rcc).with_sleep(&mut cp.SCB);
+    for _ in 0..10 {
+        led.toggle().unwrap();
+        delay.delay_ms(100_u32);
+    }
+
+    // long waits in STOP mode on the RTC wakeup timer
+    let mut delay = StopDelay::new(
+        &mut rtc,
+        &mut rcc,
+        &mut pwr,
+        &mut cp.SCB,
+        StopModeConfig::low_power(),
+    );
+    loop {
+        led.toggle().unwrap();
+        delay.delay_ms(750_u32);
+    }
+}
@@ -24,7 +24,8 @@ fn main() -> ! {
     let gpioa = dp.GPIOA.split();
 
     let c1 = gpioa.pa0;
-    let mut pwm = dp.TIM5.pwm(c1, 10.khz(), &mut rcc);
+    let timer = dp.TIM2.pwm_timer(10.khz(), &mut rcc).unwrap();
+    let (mut timer, mut pwm) = timer.channels(c1);
 
     let max = pwm.get_max_duty();
 
@@ -42,5 +43,9 @@ fn main() -> ! {
     pwm.set_duty(max / 8);
     asm::bkpt();
 
+    timer.set_frequency(1.khz()).unwrap();
+    pwm.set_duty(timer.get_max_duty() / 2);
+    asm::bkpt();
+
     loop {}
 }
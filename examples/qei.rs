@@ -10,6 +10,7 @@ extern crate stm32l1xx_hal as hal;
 
 use cortex_m_semihosting::hprintln;
 use hal::prelude::*;
+use hal::qei;
 use hal::rcc::Config;
 use hal::stm32;
 use rt::entry;
@@ -23,14 +24,17 @@ fn main() -> ! {
     let mut delay = cp.SYST.delay(rcc.clocks);
 
     let gpioa = dp.GPIOA.split();
-    let qei = dp.TIM2.qei((gpioa.pa0, gpioa.pa1), &mut rcc);
+    // filter out contact bounce on both inputs
+    let config = qei::Config::default().with_filters(6, 6);
+    let mut qei = dp
+        .TIM2
+        .qei_with_config((gpioa.pa0, gpioa.pa1), config, &mut rcc);
 
     loop {
-        let before = qei.count();
+        let before = qei.position();
         delay.delay_ms(500_u16);
-        let after = qei.count();
+        let after = qei.position();
 
-        let elapsed = after.wrapping_sub(before) as i16;
-        hprintln!("Δ: {}", elapsed);
+        hprintln!("position: {}, Δ: {}", after, after - before);
     }
 }
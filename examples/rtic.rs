@@ -20,7 +20,7 @@ use hal::gpio::{Output, PushPull};
 use hal::prelude::*;
 use hal::rcc::Config;
 use hal::stm32;
-use hal::timer::Timer;
+use hal::timer::{Event, Timer};
 
 #[app(device = hal::stm32, peripherals = true)]
 mod app {
@@ -47,7 +47,7 @@ mod app {
         let gpiob = cx.device.GPIOB.split();
         let mut timer = cx.device.TIM2.timer(1.hz(), &mut rcc);
 
-        timer.listen();
+        timer.listen(Event::Update);
         cx.device.EXTI.listen(0, TriggerEdge::Rising);
 
         let tick_led = gpiob.pb6.into_push_pull_output();
@@ -0,0 +1,52 @@
+#![deny(warnings)]
+#![no_main]
+#![no_std]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_semihosting;
+extern crate rtic;
+extern crate stm32l1xx_hal as hal;
+
+use rtic::app;
+
+#[app(device = hal::stm32, peripherals = true, dispatchers = [EXTI0])]
+mod app {
+    use embedded_hal::digital::v2::ToggleableOutputPin;
+    use fugit::ExtU64;
+    use hal::gpio::gpiob::PB6;
+    use hal::gpio::{Output, PushPull};
+    use hal::monotonic::TimerMonotonic;
+    use hal::prelude::*;
+    use hal::rcc::Config;
+    use hal::stm32;
+
+    #[monotonic(binds = TIM3, default = true)]
+    type Mono = TimerMonotonic<stm32::TIM3, 1_000>;
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {
+        led: PB6<Output<PushPull>>,
+    }
+
+    #[init]
+    fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
+        let mut rcc = cx.device.RCC.freeze(Config::hsi());
+        let mono = cx.device.TIM3.monotonic(&mut rcc).unwrap();
+
+        let gpiob = cx.device.GPIOB.split();
+        let led = gpiob.pb6.into_push_pull_output();
+
+        blink::spawn_after(500.millis()).unwrap();
+        (Shared {}, Local { led }, init::Monotonics(mono))
+    }
+
+    #[task(local = [led])]
+    fn blink(cx: blink::Context) {
+        cx.local.led.toggle().unwrap();
+        blink::spawn_after(500.millis()).unwrap();
+    }
+}
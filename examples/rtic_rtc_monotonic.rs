@@ -0,0 +1,83 @@
+#![deny(warnings)]
+#![no_main]
+#![no_std]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_semihosting;
+extern crate rtic;
+extern crate stm32l1xx_hal as hal;
+
+use rtic::app;
+
+#[app(device = hal::stm32, peripherals = true, dispatchers = [EXTI0])]
+mod app {
+    use cortex_m::peripheral::SCB;
+    use embedded_hal::digital::v2::ToggleableOutputPin;
+    use fugit::ExtU64;
+    use hal::gpio::gpiob::PB6;
+    use hal::gpio::{Output, PushPull};
+    use hal::monotonic::RtcMonotonic;
+    use hal::prelude::*;
+    use hal::pwr::{Pwr, StopModeConfig};
+    use hal::rcc::Config;
+    use hal::rtc::{Lse, Rtc};
+
+    // 1024 ticks per second from the 32.768 kHz LSE
+    #[monotonic(binds = RTC_ALARM, default = true)]
+    type Mono = RtcMonotonic<Lse, 1024>;
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {
+        led: PB6<Output<PushPull>>,
+        pwr: Pwr,
+        scb: SCB,
+    }
+
+    #[init]
+    fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
+        let dp = cx.device;
+        let mut pwr = dp.PWR;
+        let mut exti = dp.EXTI;
+
+        // MSI is also the clock after waking up from STOP, so there is nothing
+        // to restore in the tasks
+        let _rcc = dp.RCC.freeze(Config::default());
+        let rtc = Rtc::new(dp.RTC, &mut pwr);
+        let mono = RtcMonotonic::new(rtc, &mut exti).unwrap();
+
+        let gpiob = dp.GPIOB.split();
+        let led = gpiob.pb6.into_push_pull_output();
+
+        blink::spawn_after(1.secs()).unwrap();
+        (
+            Shared {},
+            Local {
+                led,
+                pwr: pwr.constrain(),
+                scb: cx.core.SCB,
+            },
+            init::Monotonics(mono),
+        )
+    }
+
+    #[task(local = [led])]
+    fn blink(cx: blink::Context) {
+        cx.local.led.toggle().unwrap();
+        blink::spawn_after(1.secs()).unwrap();
+    }
+
+    #[idle(local = [pwr, scb])]
+    fn idle(cx: idle::Context) -> ! {
+        loop {
+            // the RTC alarm wakes the core up in time for the next task
+            cx.local
+                .pwr
+                .stop_mode(StopModeConfig::ultra_low_power(), cx.local.scb);
+            cortex_m::asm::wfi();
+        }
+    }
+}
@@ -11,10 +11,11 @@ extern crate stm32l1xx_hal as hal;
 use core::cell::RefCell;
 use core::ops::DerefMut;
 use cortex_m::interrupt::Mutex;
+use fugit::MillisDurationU32;
 use hal::prelude::*;
 use hal::rcc::Config;
 use hal::stm32::{self, interrupt, Interrupt};
-use hal::timer::Timer;
+use hal::timer::{Event, Timer};
 use rt::entry;
 use sh::hprintln;
 
@@ -26,7 +27,9 @@ fn main() -> ! {
     let mut rcc = dp.RCC.freeze(Config::hsi());
 
     let mut timer = dp.TIM2.timer(1.hz(), &mut rcc);
-    timer.listen();
+    let period = timer.start_period(MillisDurationU32::millis(500)).unwrap();
+    hprintln!("period: {}", period);
+    timer.listen(Event::Update);
 
     unsafe { cortex_m::peripheral::NVIC::unmask(Interrupt::TIM2) };
 
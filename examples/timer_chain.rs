@@ -0,0 +1,36 @@
+#![deny(warnings)]
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_semihosting;
+extern crate stm32l1xx_hal as hal;
+
+use cortex_m_semihosting::hprintln;
+use hal::prelude::*;
+use hal::rcc::Config;
+use hal::stm32;
+use rt::entry;
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let cp = cortex_m::Peripherals::take().unwrap();
+
+    let mut rcc = dp.RCC.freeze(Config::hsi());
+    let mut delay = cp.SYST.delay(rcc.clocks);
+
+    // TIM3 counts microseconds, TIM4 counts TIM3 overflows
+    let tim3 = dp.TIM3.timer(1.hz(), &mut rcc);
+    let tim4 = dp.TIM4.timer(1.hz(), &mut rcc);
+    let counter = tim3.chain(tim4, 1.mhz()).unwrap();
+
+    loop {
+        let before = counter.count();
+        delay.delay_ms(500_u16);
+        let elapsed = counter.count().wrapping_sub(before);
+        hprintln!("{} us", elapsed);
+    }
+}
@@ -20,13 +20,21 @@ fn main() -> ! {
     let dp = stm32::Peripherals::take().unwrap();
     let cp = cortex_m::Peripherals::take().unwrap();
 
-    let rcc = dp.RCC.freeze(Config::hsi());
+    let mut rcc = dp.RCC.freeze(Config::hsi());
     let mut delay = cp.SYST.delay(rcc.clocks);
 
-    hprintln!("Starting watchdog");
+    // an independent watchdog reset shows up here on the next boot
+    let mut pwr = dp.PWR.constrain();
+    hprintln!("Reset cause: {:?}", rcc.reset_reason(&mut pwr).cause());
+
+    // the LSI is only specified within 26 to 56 kHz, measure it with TIM10
+    let mut tim10 = dp.TIM10;
+    let lsi = rcc.measure_lsi(&mut tim10).unwrap();
+    hprintln!("Starting watchdog, LSI at {} Hz", lsi.0);
 
     //let mut watchdog = dp.WWDG.watchdog(&mut rcc);
     let mut watchdog = dp.IWDG.watchdog();
+    watchdog.set_lsi_frequency(lsi);
     watchdog.start(100.ms());
 
     delay.delay(60.ms());
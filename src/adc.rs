@@ -1,6 +1,6 @@
 //! # Analog to Digital converter
 use crate::gpio::*;
-use crate::rcc::Rcc;
+use crate::rcc::{self, Enable, Rcc};
 use crate::stm32::ADC;
 use core::ptr;
 use hal::adc::{Channel, OneShot};
@@ -72,7 +72,7 @@ impl Adc {
         while rcc.rb.cr.read().hsirdy().bit_is_clear() {}
 
         // Enable ADC clocks
-        rcc.rb.apb2enr.modify(|_, w| w.adc1en().set_bit());
+        ADC::enable(rcc);
 
         Self {
             rb: adc,
@@ -82,6 +82,11 @@ impl Adc {
         }
     }
 
+    /// Sets whether the ADC clock runs in sleep mode, see [`rcc::set_sleep_clock`]
+    pub fn set_sleep_clock(&mut self, enabled: bool) {
+        rcc::set_sleep_clock::<ADC>(enabled);
+    }
+
     /// Set the Adc sampling time
     pub fn set_sample_time(&mut self, t_samp: SampleTime) {
         self.sample_time = t_samp;
@@ -4,7 +4,7 @@ use core::mem;
 
 use crate::gpio::gpioa::{PA4, PA5};
 use crate::gpio::{Floating, Input};
-use crate::rcc::Rcc;
+use crate::rcc::{Enable, Rcc, Reset};
 use crate::stm32::DAC;
 
 pub struct C1;
@@ -39,12 +39,9 @@ pub fn dac<PINS>(_dac: DAC, _pins: PINS, rcc: &mut Rcc) -> PINS::Output
 where
     PINS: Pins<DAC>,
 {
-    // Enable DAC clocks
-    rcc.rb.apb1enr.modify(|_, w| w.dacen().set_bit());
-
-    // Reset DAC
-    rcc.rb.apb1rstr.modify(|_, w| w.dacrst().set_bit());
-    rcc.rb.apb1rstr.modify(|_, w| w.dacrst().clear_bit());
+    // Enable and reset DAC
+    DAC::enable(rcc);
+    DAC::reset(rcc);
 
     // SAFETY: This is a zero-sized type and can be safely created
     unsafe { mem::MaybeUninit::zeroed().assume_init() }
@@ -1,9 +1,12 @@
 //! Delays
-use crate::rcc::Clocks;
+use crate::pwr::{Pwr, StopModeConfig};
+use crate::rcc::{Clocks, Rcc};
+use crate::rtc::{self, Event, Rtc};
 use crate::time::MicroSeconds;
 use cast::u32;
+use cortex_m::asm;
 use cortex_m::peripheral::syst::SystClkSource;
-use cortex_m::peripheral::SYST;
+use cortex_m::peripheral::{SCB, SYST};
 use hal::blocking::delay::{DelayMs, DelayUs};
 
 pub trait DelayExt {
@@ -17,8 +20,11 @@ impl DelayExt for SYST {
 }
 
 /// System timer (SysTick) as a delay provider
+///
+/// Delays are computed in core clock cycles, so they stay accurate at the
+/// sub-megahertz MSI ranges.
 pub struct Delay {
-    ticks_per_us: u32,
+    sys_clk: u32,
     syst: SYST,
 }
 
@@ -26,10 +32,10 @@ impl Delay {
     /// Configures the system timer (SysTick) as a delay provider
     pub fn new(mut syst: SYST, clocks: Clocks) -> Self {
         syst.set_clock_source(SystClkSource::Core);
-        let freq = clocks.sys_clk().0;
-        assert!(freq > 1_000_000_u32);
-        let ticks_per_us = freq / 1_000_000_u32;
-        Delay { syst, ticks_per_us }
+        Delay {
+            syst,
+            sys_clk: clocks.sys_clk().0,
+        }
     }
     pub fn delay<T>(&mut self, delay: T)
     where
@@ -38,15 +44,41 @@ impl Delay {
         self.delay_us(delay.into().0)
     }
 
+    /// Keeps the delays accurate after the system clock changed to `clocks`
+    pub fn set_clocks(&mut self, clocks: &Clocks) {
+        self.sys_clk = clocks.sys_clk().0;
+    }
+
     /// Releases the system timer (SysTick) resource
     pub fn free(self) -> SYST {
         self.syst
     }
+
+    /// Waits for `delay` periods of `1 / scale` seconds
+    fn wait(&mut self, delay: u32, scale: u64) {
+        let cycles = u64::from(delay) * u64::from(self.sys_clk);
+        self.wait_cycles(cycles.div_ceil(scale));
+    }
+
+    /// Waits for at least `cycles` core clock cycles
+    fn wait_cycles(&mut self, mut cycles: u64) {
+        const MAX_RVR: u64 = 0x00FF_FFFF;
+        while cycles > 0 {
+            // the counter does not run with a zero reload value
+            let rvr = cycles.clamp(1, MAX_RVR);
+            self.syst.set_reload(rvr as u32);
+            self.syst.clear_current();
+            self.syst.enable_counter();
+            cycles = cycles.saturating_sub(rvr);
+            while !self.syst.has_wrapped() {}
+            self.syst.disable_counter();
+        }
+    }
 }
 
 impl DelayMs<u32> for Delay {
     fn delay_ms(&mut self, ms: u32) {
-        self.delay_us(ms * 1_000);
+        self.wait(ms, 1_000);
     }
 }
 
@@ -64,21 +96,7 @@ impl DelayMs<u8> for Delay {
 
 impl DelayUs<u32> for Delay {
     fn delay_us(&mut self, us: u32) {
-        const MAX_RVR: u32 = 0x00FF_FFFF;
-        let mut total_rvr = self.ticks_per_us * us;
-        while total_rvr > 0 {
-            let current_rvr = if total_rvr <= MAX_RVR {
-                total_rvr
-            } else {
-                MAX_RVR
-            };
-            self.syst.set_reload(current_rvr);
-            self.syst.clear_current();
-            self.syst.enable_counter();
-            total_rvr -= current_rvr;
-            while !self.syst.has_wrapped() {}
-            self.syst.disable_counter();
-        }
+        self.wait(us, 1_000_000);
     }
 }
 
@@ -93,3 +111,88 @@ impl DelayUs<u8> for Delay {
         self.delay_us(u32(us))
     }
 }
+
+/// RTC wakeup timer as a delay provider that waits in STOP mode
+///
+/// Every delay arms the wakeup timer, enters STOP mode and restores the clocks
+/// with [`Rcc::reconfigure_after_stop`] once it fires. The resolution is 16 RTC
+/// clock periods, about 0.5 ms with the LSE, and delays longer than 65536 of
+/// those periods wake up in between. With the LSI, which varies a lot between
+/// parts, the delays follow the frequency found by [`Rcc::measure_lsi`].
+pub struct StopDelay<'a, CS> {
+    rtc: &'a mut Rtc<CS>,
+    rcc: &'a mut Rcc,
+    pwr: &'a mut Pwr,
+    scb: &'a mut SCB,
+    config: StopModeConfig,
+}
+
+impl<'a, CS> StopDelay<'a, CS>
+where
+    CS: rtc::ClockSource,
+{
+    /// Creates a delay provider entering STOP mode with `config`
+    pub fn new(
+        rtc: &'a mut Rtc<CS>,
+        rcc: &'a mut Rcc,
+        pwr: &'a mut Pwr,
+        scb: &'a mut SCB,
+        config: StopModeConfig,
+    ) -> Self {
+        StopDelay {
+            rtc,
+            rcc,
+            pwr,
+            scb,
+            config,
+        }
+    }
+
+    fn stop(&mut self, ms: u32) {
+        let rtc_clk = CS::frequency(&self.rcc.clocks);
+        let mut ticks = (u64::from(ms) * u64::from(rtc_clk / 16)).div_ceil(1_000);
+        if ticks == 0 {
+            return;
+        }
+        while ticks > 0 {
+            let chunk = ticks.min(u64::from(u16::MAX));
+            self.rtc.arm_wakeup_event(chunk as u16);
+            self.pwr.stop_mode(self.config, self.scb);
+            while !self.rtc.is_wakeup_pending() {
+                asm::wfe();
+            }
+            self.scb.clear_sleepdeep();
+            self.rtc.disable_wakeup();
+            self.rtc.unpend(Event::Wakeup);
+            ticks -= chunk;
+        }
+        self.rcc.reconfigure_after_stop();
+    }
+}
+
+impl<CS> DelayMs<u32> for StopDelay<'_, CS>
+where
+    CS: rtc::ClockSource,
+{
+    fn delay_ms(&mut self, ms: u32) {
+        self.stop(ms);
+    }
+}
+
+impl<CS> DelayMs<u16> for StopDelay<'_, CS>
+where
+    CS: rtc::ClockSource,
+{
+    fn delay_ms(&mut self, ms: u16) {
+        self.stop(u32(ms));
+    }
+}
+
+impl<CS> DelayMs<u8> for StopDelay<'_, CS>
+where
+    CS: rtc::ClockSource,
+{
+    fn delay_ms(&mut self, ms: u8) {
+        self.stop(u32(ms));
+    }
+}
@@ -20,7 +20,7 @@ pub trait DmaExt {
 }
 
 macro_rules! dma {
-    ($($DMAX:ident: ($dmaX:ident, $dmaXen:ident, $dmaXrst:ident, {
+    ($($DMAX:ident: ($dmaX:ident, {
         $($CX:ident: (
             $ccrX:ident,
             $CCRX:ident,
@@ -41,7 +41,7 @@ macro_rules! dma {
             pub mod $dmaX {
                 use crate::stm32::{$DMAX};
                 use crate::dma::{DmaExt, Event};
-                use crate::rcc::Rcc;
+                use crate::rcc::{Enable, Rcc};
 
                 #[derive(Debug)]
                 #[non_exhaustive]
@@ -81,7 +81,7 @@ macro_rules! dma {
                     type Channels = Channels;
 
                     fn dma(self, rcc: &mut Rcc) -> Channels {
-                        rcc.rb.ahbenr.modify(|_, w| w.$dmaXen().set_bit());
+                        $DMAX::enable(rcc);
                         // reset the DMA control registers (stops all on-going transfers)
                         $(
                             self.$ccrX.reset();
@@ -95,7 +95,7 @@ macro_rules! dma {
 }
 
 dma! {
-    DMA1: (dma1, dma1en, dma1rst, {
+    DMA1: (dma1, {
         C1: (
             ccr1, CCR1,
             cndtr1, CNDTR1,
@@ -155,8 +155,10 @@ dma! {
     }),
 }
 
+// Category 3 and higher parts only
+#[cfg(feature = "_cat3plus")]
 dma! {
-    DMA2: (dma2, dma2en, dma2rst, {
+    DMA2: (dma2, {
         C1: (
             ccr1, CCR1,
             cndtr1, CNDTR1,
@@ -66,7 +66,7 @@ pub enum AltMode {
 }
 
 macro_rules! gpio {
-    ($GPIOX:ident, $gpiox:ident, $iopxenr:ident, $PXx:ident, [
+    ($GPIOX:ident, $gpiox:ident, $PXx:ident, [
         $($PXi:ident: ($pxi:ident, $i:expr, $MODE:ty),)+
     ]) => {
         /// GPIO
@@ -77,6 +77,7 @@ macro_rules! gpio {
             use hal::digital::v2::{toggleable, InputPin, OutputPin, StatefulOutputPin};
             use crate::stm32::$GPIOX;
             use crate::stm32::RCC;
+            use crate::rcc::Enable;
             use super::{
                 Floating, GpioExt, Input, OpenDrain, Output, Speed,
                 PullDown, PullUp, PushPull, AltMode, Analog
@@ -96,7 +97,7 @@ macro_rules! gpio {
                 fn split(self) -> Parts {
                     // NOTE(unsafe) This executes only during initialisation
                     let rcc = unsafe { &(*RCC::ptr()) };
-                    rcc.ahbenr.modify(|_, w| w.$iopxenr().set_bit());
+                    $GPIOX::enable(rcc);
 
                     Parts {
                         $(
@@ -533,7 +534,7 @@ macro_rules! gpio {
     }
 }
 
-gpio!(GPIOA, gpioa, gpiopaen, PA, [
+gpio!(GPIOA, gpioa, PA, [
     PA0: (pa0, 0, Input<Floating>),
     PA1: (pa1, 1, Input<Floating>),
     PA2: (pa2, 2, Input<Floating>),
@@ -552,7 +553,7 @@ gpio!(GPIOA, gpioa, gpiopaen, PA, [
     PA15: (pa15, 15, Input<Floating>),
 ]);
 
-gpio!(GPIOB, gpiob, gpiopben, PB, [
+gpio!(GPIOB, gpiob, PB, [
     PB0: (pb0, 0, Input<Floating>),
     PB1: (pb1, 1, Input<Floating>),
     PB2: (pb2, 2, Input<Floating>),
@@ -571,7 +572,7 @@ gpio!(GPIOB, gpiob, gpiopben, PB, [
     PB15: (pb15, 15, Input<Floating>),
 ]);
 
-gpio!(GPIOC, gpioc, gpiopcen, PC, [
+gpio!(GPIOC, gpioc, PC, [
     PC0: (pc0, 0, Input<Floating>),
     PC1: (pc1, 1, Input<Floating>),
     PC2: (pc2, 2, Input<Floating>),
@@ -590,7 +591,7 @@ gpio!(GPIOC, gpioc, gpiopcen, PC, [
     PC15: (pc15, 15, Input<Floating>),
 ]);
 
-gpio!(GPIOD, gpiod, gpiopden, PD, [
+gpio!(GPIOD, gpiod, PD, [
     PD0: (pd0, 0, Input<Floating>),
     PD1: (pd1, 1, Input<Floating>),
     PD2: (pd2, 2, Input<Floating>),
@@ -610,7 +611,7 @@ gpio!(GPIOD, gpiod, gpiopden, PD, [
 ]);
 
 #[cfg(any(feature = "stm32l151", feature = "stm32l152", feature = "stm32l162"))]
-gpio!(GPIOE, gpioe, gpiopeen, PE, [
+gpio!(GPIOE, gpioe, PE, [
     PE0: (pe0, 0, Input<Floating>),
     PE1: (pe1, 1, Input<Floating>),
     PE2: (pe2, 2, Input<Floating>),
@@ -630,7 +631,7 @@ gpio!(GPIOE, gpioe, gpiopeen, PE, [
 ]);
 
 #[cfg(any(feature = "stm32l151", feature = "stm32l152", feature = "stm32l162"))]
-gpio!(GPIOF, gpiof, gpiopfen, PF, [
+gpio!(GPIOF, gpiof, PF, [
     PF0: (pf0, 0, Input<Floating>),
     PF1: (pf1, 1, Input<Floating>),
     PF2: (pf2, 2, Input<Floating>),
@@ -650,7 +651,7 @@ gpio!(GPIOF, gpiof, gpiopfen, PF, [
 ]);
 
 #[cfg(any(feature = "stm32l151", feature = "stm32l152", feature = "stm32l162"))]
-gpio!(GPIOG, gpiog, gpiopgen, PG, [
+gpio!(GPIOG, gpiog, PG, [
     PG0: (pg0, 0, Input<Floating>),
     PG1: (pg1, 1, Input<Floating>),
     PG2: (pg2, 2, Input<Floating>),
@@ -670,7 +671,7 @@ gpio!(GPIOG, gpiog, gpiopgen, PG, [
 ]);
 
 #[cfg(any(feature = "stm32l151", feature = "stm32l152", feature = "stm32l162"))]
-gpio!(GPIOH, gpioh, gpiophen, PH, [
+gpio!(GPIOH, gpioh, PH, [
     PH0: (ph0, 0, Input<Floating>),
     PH1: (ph1, 1, Input<Floating>),
     PH2: (ph2, 2, Input<Floating>),
@@ -4,7 +4,7 @@ use hal::blocking::i2c::{Read, Write, WriteRead};
 use crate::gpio::gpiob::{PB10, PB11, PB6, PB7, PB8, PB9};
 use crate::gpio::{AltMode, OpenDrain, Output};
 use crate::prelude::*;
-use crate::rcc::Rcc;
+use crate::rcc::{self, BusClock, Clocks, Enable, Rcc, Reset};
 use crate::stm32::{I2C1, I2C2};
 use crate::time::Hertz;
 
@@ -12,6 +12,7 @@ use crate::time::Hertz;
 pub struct I2c<I2C, PINS> {
     i2c: I2C,
     pins: PINS,
+    speed: Hertz,
 }
 
 pub trait Pins<I2c> {
@@ -46,7 +47,7 @@ pub enum Error {
 }
 
 macro_rules! i2c {
-    ($I2CX:ident, $i2cx:ident, $i2cxen:ident, $i2crst:ident) => {
+    ($I2CX:ident, $i2cx:ident) => {
         impl<PINS> I2c<$I2CX, PINS> {
             pub fn $i2cx(i2c: $I2CX, pins: PINS, speed: Hertz, rcc: &mut Rcc) -> Self
             where
@@ -56,36 +57,46 @@ macro_rules! i2c {
                 let speed: Hertz = speed.into();
 
                 // Enable clock for I2C
-                rcc.rb.apb1enr.modify(|_, w| w.$i2cxen().set_bit());
+                $I2CX::enable(rcc);
 
                 // Reset I2C
-                rcc.rb.apb1rstr.modify(|_, w| w.$i2crst().set_bit());
-                rcc.rb.apb1rstr.modify(|_, w| w.$i2crst().clear_bit());
+                $I2CX::reset(rcc);
 
+                let mut i2c = I2c { i2c, pins, speed };
+                i2c.set_clocks(&rcc.clocks);
+                i2c
+            }
+
+            /// Keeps the bus speed after the bus clock changed to `clocks`
+            ///
+            /// Call it between transfers.
+            pub fn set_clocks(&mut self, clocks: &Clocks) {
                 // Make sure the I2C unit is disabled so we can configure it
-                i2c.cr1.modify(|_, w| w.pe().clear_bit());
+                self.i2c.cr1.modify(|_, w| w.pe().clear_bit());
 
                 // Calculate settings for I2C speed modes
-                let clock = rcc.clocks.apb1_clk().0;
+                let clock = $I2CX::clock(clocks).0;
                 let freq = clock / 1_000_000;
                 assert!((2..=50).contains(&freq));
 
                 // Configure bus frequency into I2C peripheral
-                i2c.cr2.write(|w| unsafe { w.freq().bits(freq as u8) });
+                self.i2c
+                    .cr2
+                    .modify(|_, w| unsafe { w.freq().bits(freq as u8) });
 
-                let trise = if speed <= 100_u32.khz().into() {
+                let trise = if self.speed <= 100_u32.khz().into() {
                     freq + 1
                 } else {
                     (freq * 300) / 1000 + 1
                 };
 
                 // Configure correct rise times
-                i2c.trise.write(|w| w.trise().bits(trise as u8));
+                self.i2c.trise.write(|w| w.trise().bits(trise as u8));
 
                 // I2C clock control calculation
-                if speed <= 100_u32.khz().into() {
+                if self.speed <= 100_u32.khz().into() {
                     let ccr = {
-                        let ccr = clock / (speed.0 * 2);
+                        let ccr = clock / (self.speed.0 * 2);
                         if ccr < 4 {
                             4
                         } else {
@@ -94,7 +105,7 @@ macro_rules! i2c {
                     };
 
                     // Set clock to standard mode with appropriate parameters for selected speed
-                    i2c.ccr.write(|w| unsafe {
+                    self.i2c.ccr.write(|w| unsafe {
                         w.f_s()
                             .clear_bit()
                             .duty()
@@ -105,28 +116,31 @@ macro_rules! i2c {
                 } else {
                     const DUTYCYCLE: u8 = 0;
                     if DUTYCYCLE == 0 {
-                        let ccr = clock / (speed.0 * 3);
+                        let ccr = clock / (self.speed.0 * 3);
                         let ccr = if ccr < 1 { 1 } else { ccr };
 
                         // Set clock to fast mode with appropriate parameters for selected speed (2:1 duty cycle)
-                        i2c.ccr.write(|w| unsafe {
+                        self.i2c.ccr.write(|w| unsafe {
                             w.f_s().set_bit().duty().clear_bit().ccr().bits(ccr as u16)
                         });
                     } else {
-                        let ccr = clock / (speed.0 * 25);
+                        let ccr = clock / (self.speed.0 * 25);
                         let ccr = if ccr < 1 { 1 } else { ccr };
 
                         // Set clock to fast mode with appropriate parameters for selected speed (16:9 duty cycle)
-                        i2c.ccr.write(|w| unsafe {
+                        self.i2c.ccr.write(|w| unsafe {
                             w.f_s().set_bit().duty().set_bit().ccr().bits(ccr as u16)
                         });
                     }
                 }
 
                 // Enable the I2C processing
-                i2c.cr1.modify(|_, w| w.pe().set_bit());
+                self.i2c.cr1.modify(|_, w| w.pe().set_bit());
+            }
 
-                I2c { i2c, pins }
+            /// Sets whether the I2C clock runs in sleep mode, see [`rcc::set_sleep_clock`]
+            pub fn set_sleep_clock(&mut self, enabled: bool) {
+                rcc::set_sleep_clock::<$I2CX>(enabled);
             }
 
             pub fn release(self) -> ($I2CX, PINS) {
@@ -314,5 +328,5 @@ pub trait I2cExt<I2C> {
         T: Into<Hertz>;
 }
 
-i2c!(I2C1, i2c1, i2c1en, i2c1rst);
-i2c!(I2C2, i2c2, i2c2en, i2c2rst);
+i2c!(I2C1, i2c1);
+i2c!(I2C2, i2c2);
@@ -42,6 +42,8 @@ pub mod exti;
 pub mod gpio;
 pub mod i2c;
 pub mod mco;
+#[cfg(feature = "rtic")]
+pub mod monotonic;
 pub mod prelude;
 pub mod pwm;
 pub mod pwr;
@@ -51,5 +53,7 @@ pub mod rtc;
 pub mod serial;
 pub mod spi;
 pub mod time;
+#[cfg(feature = "_time-driver")]
+pub mod time_driver;
 pub mod timer;
 pub mod watchdog;
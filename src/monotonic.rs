@@ -0,0 +1,302 @@
+//! RTIC monotonic timers
+//!
+//! The counter of a general-purpose timer is extended to 64 bits by counting
+//! half periods: the update event marks the overflow and the CC2 event fires
+//! half-way, so the extension stays consistent even when [`TimerMonotonic::now`]
+//! runs with interrupts disabled for up to half a counter period. CC1 is used as
+//! the RTIC compare.
+//!
+//! The prescaler is computed once from the timer clock, so the timer monotonics
+//! must not run across [`crate::rcc::Rcc::set_config`]: after a clock change
+//! their tick rate is wrong. [`RtcMonotonic`] does not depend on the bus clocks.
+//!
+//! Timers stop in STOP mode, [`RtcMonotonic`] instead counts on the RTC calendar
+//! and sub-second counter and uses alarm A as compare, so the scheduler can keep
+//! time while the core sleeps in STOP between tasks.
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use fugit::{TimerDurationU64, TimerInstantU64};
+use rtic_monotonic::Monotonic;
+
+use crate::bb;
+use crate::rcc::{self, BusTimerClock, Enable, Rcc, Reset};
+use crate::rtc::{ClockSource, Rtc};
+#[cfg(feature = "_cat3plus")]
+use crate::stm32::TIM5;
+use crate::stm32::{EXTI, TIM2, TIM3, TIM4};
+use crate::timer::Error;
+
+const UIF: u32 = 1 << 0;
+const CC1IF: u32 = 1 << 1;
+const CC2IF: u32 = 1 << 2;
+
+pub trait MonotonicExt: Sized {
+    /// Turns the timer into an RTIC monotonic counting at `FREQ` Hz
+    ///
+    /// `FREQ` has to divide the timer clock with a 16-bit prescaler.
+    fn monotonic<const FREQ: u32>(self, rcc: &mut Rcc)
+        -> Result<TimerMonotonic<Self, FREQ>, Error>;
+}
+
+/// General-purpose timer as a 64-bit RTIC monotonic with `FREQ` ticks per second
+pub struct TimerMonotonic<TIM, const FREQ: u32> {
+    tim: TIM,
+    period: u32,
+}
+
+macro_rules! monotonics {
+    ($($TIM:ident: $bits:expr,)+) => {
+        $(
+            impl MonotonicExt for $TIM {
+                fn monotonic<const FREQ: u32>(
+                    self,
+                    rcc: &mut Rcc,
+                ) -> Result<TimerMonotonic<Self, FREQ>, Error> {
+                    TimerMonotonic::<$TIM, FREQ>::new(self, rcc)
+                }
+            }
+
+            impl<const FREQ: u32> TimerMonotonic<$TIM, FREQ> {
+                const HALF: u32 = 1 << ($bits - 1);
+
+                fn new(tim: $TIM, rcc: &mut Rcc) -> Result<Self, Error> {
+                    let clk = $TIM::timer_clock(&rcc.clocks).0;
+                    if FREQ == 0 || !clk.is_multiple_of(FREQ) || clk / FREQ > 1 << 16 {
+                        return Err(Error::InvalidTickRate);
+                    }
+
+                    $TIM::enable(rcc);
+                    $TIM::reset(rcc);
+                    rcc::set_sleep_clock::<$TIM>(true);
+
+                    tim.psc.write(|w| w.psc().bits((clk / FREQ - 1) as u16));
+                    tim.arr.write(|w| unsafe { w.bits(u32::MAX >> (32 - $bits)) });
+                    tim.ccr2().write(|w| unsafe { w.bits(Self::HALF) });
+                    // load the prescaler without raising an update interrupt
+                    tim.cr1.modify(|_, w| w.urs().set_bit());
+                    tim.egr.write(|w| w.ug().set_bit());
+                    tim.dier
+                        .modify(|_, w| w.uie().set_bit().cc1ie().set_bit().cc2ie().set_bit());
+
+                    Ok(TimerMonotonic { tim, period: 0 })
+                }
+
+                /// Releases the TIM peripheral
+                pub fn release(self) -> $TIM {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    self.tim.dier.reset();
+                    self.tim
+                }
+            }
+
+            impl<const FREQ: u32> Monotonic for TimerMonotonic<$TIM, FREQ> {
+                const DISABLE_INTERRUPT_ON_EMPTY_QUEUE: bool = false;
+
+                type Instant = TimerInstantU64<FREQ>;
+                type Duration = TimerDurationU64<FREQ>;
+
+                fn now(&mut self) -> Self::Instant {
+                    let period = self.period;
+                    compiler_fence(Ordering::Acquire);
+                    let counter = self.tim.cnt.read().bits();
+                    // the counter is in the first half on even periods and in the
+                    // second half on odd ones, the XOR catches up a pending half period
+                    let ticks = (u64::from(period) << ($bits - 1))
+                        + u64::from(counter ^ ((period & 1) << ($bits - 1)));
+                    TimerInstantU64::from_ticks(ticks)
+                }
+
+                fn set_compare(&mut self, instant: Self::Instant) {
+                    // compares further away than one counter period fire early, RTIC
+                    // then checks the queue and sets the compare again
+                    let ccr = instant.ticks() as u32 & (u32::MAX >> (32 - $bits));
+                    self.tim.ccr1().write(|w| unsafe { w.bits(ccr) });
+                }
+
+                fn clear_compare_flag(&mut self) {
+                    self.tim.sr.write(|w| unsafe { w.bits(!CC1IF) });
+                }
+
+                fn zero() -> Self::Instant {
+                    TimerInstantU64::from_ticks(0)
+                }
+
+                unsafe fn reset(&mut self) {
+                    self.tim.cnt.reset();
+                    self.tim.sr.write(|w| w.bits(!(UIF | CC2IF)));
+                    self.period = 0;
+                    self.tim.cr1.modify(|_, w| w.cen().set_bit());
+                }
+
+                fn on_interrupt(&mut self) {
+                    let sr = self.tim.sr.read().bits();
+                    if sr & UIF != 0 {
+                        self.tim.sr.write(|w| unsafe { w.bits(!UIF) });
+                        self.period = self.period.wrapping_add(1);
+                    }
+                    if sr & CC2IF != 0 {
+                        self.tim.sr.write(|w| unsafe { w.bits(!CC2IF) });
+                        self.period = self.period.wrapping_add(1);
+                    }
+                }
+            }
+        )+
+    }
+}
+
+monotonics! {
+    TIM2: 16,
+    TIM3: 16,
+    TIM4: 16,
+}
+
+#[cfg(feature = "_cat3plus")]
+monotonics! {
+    TIM5: 32,
+}
+
+const SECONDS_PER_DAY: u64 = 86_400;
+/// Days before the first of each month in a common year
+const DAYS_BEFORE_MONTH: [u16; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+
+/// RTC as a 64-bit RTIC monotonic with `FREQ` ticks per second that keeps
+/// counting in STOP mode
+///
+/// Ticks are counted from the start of calendar year 0 of the RTC, so the calendar
+/// keeps its wall-clock time and the count survives resets while the backup domain
+/// is powered. It wraps when the two-digit calendar year rolls over after 100 years.
+/// Bind the monotonic to the `RTC_ALARM` interrupt.
+pub struct RtcMonotonic<CS, const FREQ: u32> {
+    rtc: Rtc<CS>,
+}
+
+impl<CS: ClockSource, const FREQ: u32> RtcMonotonic<CS, FREQ> {
+    /// Turns the RTC into a monotonic counting at `FREQ` Hz
+    ///
+    /// `FREQ` has to divide the RTC clock by 1 to 128 and be at most 32768 Hz. The
+    /// prescalers are reprogrammed, which restarts the current second.
+    pub fn new(mut rtc: Rtc<CS>, exti: &mut EXTI) -> Result<Self, Error> {
+        if FREQ == 0
+            || FREQ > 1 << 15
+            || !CS::FREQUENCY.is_multiple_of(FREQ)
+            || CS::FREQUENCY / FREQ > 128
+        {
+            return Err(Error::InvalidTickRate);
+        }
+        rtc.set_prescalers((FREQ - 1) as u16, (CS::FREQUENCY / FREQ - 1) as u8);
+
+        let regs = &rtc.regs;
+        regs.wpr.write(|w| unsafe { w.bits(0xCA) });
+        regs.wpr.write(|w| unsafe { w.bits(0x53) });
+        // read the counters directly, shadow registers are stale after STOP mode
+        regs.cr
+            .modify(|_, w| w.bypshad().set_bit().alrae().clear_bit());
+        regs.cr.modify(|_, w| w.alraie().set_bit());
+        regs.wpr.write(|w| unsafe { w.bits(0xFF) });
+
+        // alarm A on EXTI line 17, as an interrupt and as an event for WFE
+        bb::set(&exti.rtsr, 17);
+        bb::set(&exti.imr, 17);
+        bb::set(&exti.emr, 17);
+
+        Ok(RtcMonotonic { rtc })
+    }
+
+    /// Releases the RTC
+    pub fn release(self) -> Rtc<CS> {
+        let regs = &self.rtc.regs;
+        regs.wpr.write(|w| unsafe { w.bits(0xCA) });
+        regs.wpr.write(|w| unsafe { w.bits(0x53) });
+        regs.cr.modify(|_, w| {
+            w.alrae().clear_bit();
+            w.alraie().clear_bit();
+            w.bypshad().clear_bit()
+        });
+        regs.wpr.write(|w| unsafe { w.bits(0xFF) });
+        self.rtc
+    }
+}
+
+impl<CS: ClockSource, const FREQ: u32> Monotonic for RtcMonotonic<CS, FREQ> {
+    const DISABLE_INTERRUPT_ON_EMPTY_QUEUE: bool = false;
+
+    type Instant = TimerInstantU64<FREQ>;
+    type Duration = TimerDurationU64<FREQ>;
+
+    fn now(&mut self) -> Self::Instant {
+        let regs = &self.rtc.regs;
+        // the counters are read without shadowing, so read until two reads agree
+        let (ssr, tr, dr) = loop {
+            let ssr = regs.ssr.read().ss().bits();
+            let tr = regs.tr.read();
+            let dr = regs.dr.read();
+            if ssr == regs.ssr.read().ss().bits() && tr.bits() == regs.tr.read().bits() {
+                break (ssr, tr, dr);
+            }
+        };
+
+        let year = u64::from(dr.yt().bits() * 10 + dr.yu().bits());
+        let month = usize::from(u8::from(dr.mt().bit()) * 10 + dr.mu().bits());
+        let day = u64::from(dr.dt().bits() * 10 + dr.du().bits());
+        // the RTC counts every year divisible by 4 as leap year
+        let leap = u64::from(year.is_multiple_of(4) && month > 2);
+        let days = year * 365
+            + year.div_ceil(4)
+            + u64::from(DAYS_BEFORE_MONTH[month.clamp(1, 12) - 1])
+            + leap
+            + day.saturating_sub(1);
+
+        let seconds = u64::from(tr.ht().bits() * 10 + tr.hu().bits()) * 3600
+            + u64::from(tr.mnt().bits() * 10 + tr.mnu().bits()) * 60
+            + u64::from(tr.st().bits() * 10 + tr.su().bits());
+
+        // the sub-second counter counts down from FREQ - 1
+        let subsecond = u64::from((FREQ - 1).saturating_sub(u32::from(ssr)));
+        TimerInstantU64::from_ticks(
+            (days * SECONDS_PER_DAY + seconds) * u64::from(FREQ) + subsecond,
+        )
+    }
+
+    fn set_compare(&mut self, instant: Self::Instant) {
+        // the alarm matches the time of day, compares further away than one day
+        // fire early and RTIC sets the compare again
+        let ticks = instant.ticks();
+        let seconds = (ticks / u64::from(FREQ)) % SECONDS_PER_DAY;
+        let ss = (FREQ - 1) - (ticks % u64::from(FREQ)) as u32;
+        let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+
+        let regs = &self.rtc.regs;
+        regs.wpr.write(|w| unsafe { w.bits(0xCA) });
+        regs.wpr.write(|w| unsafe { w.bits(0x53) });
+        regs.cr.modify(|_, w| w.alrae().clear_bit());
+        while regs.isr.read().alrawf().bit_is_clear() {}
+        regs.alrmar().write(|w| unsafe {
+            w.msk4().set_bit();
+            w.ht().bits((hours / 10) as u8);
+            w.hu().bits((hours % 10) as u8);
+            w.mnt().bits((minutes / 10) as u8);
+            w.mnu().bits((minutes % 10) as u8);
+            w.st().bits((seconds / 10) as u8);
+            w.su().bits((seconds % 10) as u8)
+        });
+        regs.alrmassr()
+            .write(|w| unsafe { w.maskss().bits(15).ss().bits(ss as u16) });
+        regs.cr.modify(|_, w| w.alrae().set_bit());
+        regs.wpr.write(|w| unsafe { w.bits(0xFF) });
+    }
+
+    fn clear_compare_flag(&mut self) {
+        let exti = unsafe { &(*EXTI::ptr()) };
+        self.rtc.regs.isr.modify(|_, w| w.alraf().clear_bit());
+        bb::set(&exti.pr, 17);
+    }
+
+    fn zero() -> Self::Instant {
+        TimerInstantU64::from_ticks(0)
+    }
+
+    unsafe fn reset(&mut self) {
+        // the calendar keeps running, only drop a stale alarm
+        self.clear_compare_flag();
+    }
+}
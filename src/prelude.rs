@@ -14,6 +14,8 @@ pub use crate::exti::ExtiExt as _stm32l1xx_hal_exti_ExtiExt;
 pub use crate::exti::ExtiTrait as _stm32l1xx_hal_exti_ExtiTrait;
 pub use crate::gpio::GpioExt as _stm32l1xx_hal_gpio_GpioExt;
 pub use crate::i2c::I2cExt as _stm32l1xx_hal_i2c_I2Ext;
+#[cfg(feature = "rtic")]
+pub use crate::monotonic::MonotonicExt as _stm32l1xx_hal_monotonic_MonotonicExt;
 pub use crate::pwm::PwmExt as _stm32l1xx_hal_pwm_PwmExt;
 pub use crate::pwr::PwrExt as _stm32l1xx_hal_pwr_PwrExt;
 pub use crate::qei::QeiExt as _stm32l1xx_hal_qei_QeiExt;
@@ -22,6 +24,9 @@ pub use crate::rtc::Rtc as _stm32l1xx_hal_rtc_Rcc;
 pub use crate::spi::SpiExt as _stm32l1xx_hal_spi_SpiExt;
 pub use crate::time::MonoTimerExt as _stm32l1xx_hal_time_MonoTimerExt;
 pub use crate::time::U32Ext as _stm32l1xx_hal_time_U32Ext;
+pub use crate::timer::ChainExt as _stm32l1xx_hal_timer_ChainExt;
+pub use crate::timer::DelayExt as _stm32l1xx_hal_timer_DelayExt;
 pub use crate::timer::TimerExt as _stm32l1xx_hal_timer_TimerExt;
+pub use crate::timer::TimerPeriodExt as _stm32l1xx_hal_timer_TimerPeriodExt;
 pub use crate::watchdog::IndependedWatchdogExt as _stm32l1xx_hal_watchdog_IndependedWatchdogExt;
 pub use crate::watchdog::WindowWatchdogExt as _stm32l1xx_hal_watchdog_WindowWatchdogExt;
@@ -1,30 +1,193 @@
 use core::marker::PhantomData;
-use core::mem;
 
-use crate::gpio::gpioa::{PA0, PA1, PA2, PA3, PA6, PA7};
-use crate::gpio::gpiob::{PB0, PB1, PB6, PB7, PB8, PB9};
+use crate::bb;
+
+#[cfg(any(feature = "stm32l151", feature = "stm32l152", feature = "stm32l162"))]
+use crate::gpio::gpioe;
+use crate::gpio::{gpioa, gpiob, gpioc, gpiod};
 use crate::gpio::{AltMode, Floating, Input};
-use crate::rcc::Rcc;
-use crate::stm32::{TIM10, TIM11, TIM2, TIM3, TIM4, TIM5};
+use crate::rcc::{self, BusTimerClock, Clocks, Enable, Rcc, Reset};
+#[cfg(feature = "_cat3plus")]
+use crate::stm32::TIM5;
+use crate::stm32::{TIM10, TIM11, TIM2, TIM3, TIM4, TIM9};
 use crate::time::Hertz;
-use cast::{u16, u32};
+use crate::timer::{InternalTrigger, MasterMode, SlaveMode};
+use cast::u16;
 use hal;
 
+/// PWM error
+#[derive(Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The frequency leaves less than two timer ticks per period
+    FrequencyTooHigh,
+    /// The period does not fit into the prescaler and auto-reload registers
+    FrequencyTooLow,
+}
+
+/// Counter alignment of a PWM timer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Alignment {
+    /// The counter counts up, outputs switch on compare match and update
+    Edge = 0b00,
+    /// The counter counts up and down, compare flags are set when counting down
+    CenterDown = 0b01,
+    /// The counter counts up and down, compare flags are set when counting up
+    CenterUp = 0b10,
+    /// The counter counts up and down, compare flags are set in both directions
+    CenterUpDown = 0b11,
+}
+
+/// Output polarity of a PWM channel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Polarity {
+    /// The output is high for the duty part of the period
+    ActiveHigh,
+    /// The output is low for the duty part of the period
+    ActiveLow,
+}
+
 pub struct C1;
 pub struct C2;
 pub struct C3;
 pub struct C4;
 
+mod sealed {
+    pub trait Channels {
+        fn new() -> Self;
+    }
+
+    /// Implemented for every pair of different channels
+    pub trait Distinct<C> {}
+}
+
+macro_rules! distinct {
+    ($($A:ident: [$($B:ident),+],)+) => {
+        $(
+            $(
+                impl sealed::Distinct<$B> for $A {}
+            )+
+        )+
+    };
+}
+
+distinct! {
+    C1: [C2, C3, C4],
+    C2: [C1, C3, C4],
+    C3: [C1, C2, C4],
+    C4: [C1, C2, C3],
+}
+
+/// A pin connected to a channel of `TIM`
+pub trait Pin<TIM> {
+    /// The timer channel driving the pin
+    type Channel;
+    fn setup(&self);
+}
+
+/// A pin or a tuple of pins, possibly from different ports, driven by `TIM`
+///
+/// Every pin of a tuple has to drive a different channel, a tuple that names the
+/// same channel twice does not implement `Pins`.
 pub trait Pins<TIM> {
-    type Channels;
+    type Channels: sealed::Channels;
     fn setup(&self);
 }
 
+impl<TIM, P1, P2> Pins<TIM> for (P1, P2)
+where
+    P1: Pin<TIM>,
+    P2: Pin<TIM>,
+    P1::Channel: sealed::Distinct<P2::Channel>,
+{
+    type Channels = (Pwm<TIM, P1::Channel>, Pwm<TIM, P2::Channel>);
+
+    fn setup(&self) {
+        self.0.setup();
+        self.1.setup();
+    }
+}
+
+impl<TIM, P1, P2, P3> Pins<TIM> for (P1, P2, P3)
+where
+    P1: Pin<TIM>,
+    P2: Pin<TIM>,
+    P3: Pin<TIM>,
+    P1::Channel: sealed::Distinct<P2::Channel> + sealed::Distinct<P3::Channel>,
+    P2::Channel: sealed::Distinct<P3::Channel>,
+{
+    type Channels = (
+        Pwm<TIM, P1::Channel>,
+        Pwm<TIM, P2::Channel>,
+        Pwm<TIM, P3::Channel>,
+    );
+
+    fn setup(&self) {
+        self.0.setup();
+        self.1.setup();
+        self.2.setup();
+    }
+}
+
+impl<TIM, P1, P2, P3, P4> Pins<TIM> for (P1, P2, P3, P4)
+where
+    P1: Pin<TIM>,
+    P2: Pin<TIM>,
+    P3: Pin<TIM>,
+    P4: Pin<TIM>,
+    P1::Channel: sealed::Distinct<P2::Channel>
+        + sealed::Distinct<P3::Channel>
+        + sealed::Distinct<P4::Channel>,
+    P2::Channel: sealed::Distinct<P3::Channel> + sealed::Distinct<P4::Channel>,
+    P3::Channel: sealed::Distinct<P4::Channel>,
+{
+    type Channels = (
+        Pwm<TIM, P1::Channel>,
+        Pwm<TIM, P2::Channel>,
+        Pwm<TIM, P3::Channel>,
+        Pwm<TIM, P4::Channel>,
+    );
+
+    fn setup(&self) {
+        self.0.setup();
+        self.1.setup();
+        self.2.setup();
+        self.3.setup();
+    }
+}
+
 pub trait PwmExt: Sized {
-    fn pwm<PINS, T>(self, _: PINS, frequency: T, rcc: &mut Rcc) -> PINS::Channels
+    /// Starts the timer at `frequency` and returns the channels of `pins`
+    fn pwm<PINS, T>(self, pins: PINS, frequency: T, rcc: &mut Rcc) -> Result<PINS::Channels, Error>
     where
         PINS: Pins<Self>,
         T: Into<Hertz>;
+
+    /// Starts the timer at `frequency` and returns it as a PWM timer
+    fn pwm_timer<T>(self, frequency: T, rcc: &mut Rcc) -> Result<PwmTimer<Self>, Error>
+    where
+        T: Into<Hertz>;
+}
+
+/// A timer running in PWM mode, handing out its output channels
+///
+/// The channels are handed out once by [`PwmTimer::channels`], which turns the
+/// timer into a `PwmTimer<TIM, Split<CHANNELS>>`. That one is only released
+/// together with its channels.
+pub struct PwmTimer<TIM, STATE = Unsplit> {
+    tim: TIM,
+    clk: Hertz,
+    frequency: Hertz,
+    alignment: Alignment,
+    _state: PhantomData<STATE>,
+}
+
+/// State of a [`PwmTimer`] that has not handed out its channels
+pub struct Unsplit;
+
+/// State of a [`PwmTimer`] that has handed out `CHANNELS`
+pub struct Split<CHANNELS> {
+    _channels: PhantomData<CHANNELS>,
 }
 
 pub struct Pwm<TIM, CHANNEL> {
@@ -32,36 +195,123 @@ pub struct Pwm<TIM, CHANNEL> {
     _tim: PhantomData<TIM>,
 }
 
-macro_rules! channels {
-    ($TIMX:ident, $af:expr, $c1:ty) => {
-        impl Pins<$TIMX> for $c1 {
-            type Channels = Pwm<$TIMX, C1>;
+impl<TIM, CHANNEL> sealed::Channels for Pwm<TIM, CHANNEL> {
+    fn new() -> Self {
+        Pwm {
+            _channel: PhantomData,
+            _tim: PhantomData,
+        }
+    }
+}
+
+impl<A, B> sealed::Channels for (A, B)
+where
+    A: sealed::Channels,
+    B: sealed::Channels,
+{
+    fn new() -> Self {
+        (A::new(), B::new())
+    }
+}
+
+impl<A, B, C> sealed::Channels for (A, B, C)
+where
+    A: sealed::Channels,
+    B: sealed::Channels,
+    C: sealed::Channels,
+{
+    fn new() -> Self {
+        (A::new(), B::new(), C::new())
+    }
+}
+
+impl<A, B, C, D> sealed::Channels for (A, B, C, D)
+where
+    A: sealed::Channels,
+    B: sealed::Channels,
+    C: sealed::Channels,
+    D: sealed::Channels,
+{
+    fn new() -> Self {
+        (A::new(), B::new(), C::new(), D::new())
+    }
+}
+
+/// Computes PSC and ARR for `frequency` from the timer clock `clk`
+fn psc_arr(clk: Hertz, frequency: Hertz, alignment: Alignment) -> Result<(u16, u16), Error> {
+    if frequency.0 == 0 {
+        return Err(Error::FrequencyTooLow);
+    }
+    let (psc, arr) = if alignment == Alignment::Edge {
+        // a period takes ARR + 1 ticks
+        let ticks = clk.0 / frequency.0;
+        let psc = ticks.saturating_sub(1) >> 16;
+        (psc, (ticks / (psc + 1)).saturating_sub(1))
+    } else {
+        // the counter counts up to ARR and back down, a period takes 2 * ARR ticks
+        let ticks = clk.0 / frequency.0 / 2;
+        let psc = ticks >> 16;
+        (psc, ticks / (psc + 1))
+    };
+    if arr == 0 {
+        return Err(Error::FrequencyTooHigh);
+    }
+    let psc = u16(psc).map_err(|_| Error::FrequencyTooLow)?;
+    Ok((psc, arr as u16))
+}
 
-            fn setup(&self) {
-                self.set_alt_mode($af);
+// TIM9 has a CCER register at offset 0x20 like the other timers, but the PAC
+// does not describe it. The CCER bits are written by address through bit-banding
+// on every timer instead: CCxE is bit 4 * (x - 1) and CCxP the bit above it.
+macro_rules! ccer {
+    (TIM9) => {
+        (crate::stm32::TIM9::ptr() as *const u8).wrapping_add(0x20) as *const u32
+    };
+    ($TIMX:ident) => {
+        unsafe { core::ptr::addr_of!((*$TIMX::ptr()).ccer) }
+    };
+}
+pub(crate) use ccer;
+
+macro_rules! channel {
+    ($TIMX:ident, C1) => {
+        channel!($TIMX, C1, ccr1, ccmr1_output, oc1pe, oc1m, 0, 1);
+    };
+    ($TIMX:ident, C2) => {
+        channel!($TIMX, C2, ccr2, ccmr1_output, oc2pe, oc2m, 4, 5);
+    };
+    ($TIMX:ident, C3) => {
+        channel!($TIMX, C3, ccr3, ccmr2_output, oc3pe, oc3m, 8, 9);
+    };
+    ($TIMX:ident, C4) => {
+        channel!($TIMX, C4, ccr4, ccmr2_output, oc4pe, oc4m, 12, 13);
+    };
+    ($TIMX:ident, $C:ident, $ccrX:ident, $ccmrX_output:ident, $ocXpe:ident, $ocXm:ident, $ccXe:literal, $ccXp:literal) => {
+        impl Pwm<$TIMX, $C> {
+            /// Sets the output polarity of the channel
+            pub fn set_polarity(&mut self, polarity: Polarity) {
+                bb::write(ccer!($TIMX), $ccXp, polarity == Polarity::ActiveLow);
             }
         }
 
-        impl hal::PwmPin for Pwm<$TIMX, C1> {
+        impl hal::PwmPin for Pwm<$TIMX, $C> {
             type Duty = u16;
 
             fn disable(&mut self) {
-                unsafe {
-                    (*$TIMX::ptr()).ccer.modify(|_, w| w.cc1e().clear_bit());
-                }
+                bb::clear(ccer!($TIMX), $ccXe);
             }
 
             fn enable(&mut self) {
                 unsafe {
                     let tim = &*$TIMX::ptr();
-                    tim.ccmr1_output()
-                        .modify(|_, w| w.oc1pe().set_bit().oc1m().bits(6));
-                    tim.ccer.modify(|_, w| w.cc1e().set_bit());
+                    tim.$ccmrX_output()
+                        .modify(|_, w| w.$ocXpe().set_bit().$ocXm().bits(6));
                 }
+                bb::set(ccer!($TIMX), $ccXe);
             }
 
             fn get_duty(&self) -> u16 {
-                unsafe { (*$TIMX::ptr()).ccr1().read().ccr().bits() }
+                unsafe { (*$TIMX::ptr()).$ccrX().read().ccr().bits() }
             }
 
             fn get_max_duty(&self) -> u16 {
@@ -69,244 +319,388 @@ macro_rules! channels {
             }
 
             fn set_duty(&mut self, duty: u16) {
-                unsafe { (*$TIMX::ptr()).ccr1().write(|w| w.ccr().bits(duty)) }
+                unsafe { (*$TIMX::ptr()).$ccrX().write(|w| w.ccr().bits(duty)) }
             }
         }
     };
-    ($TIMX:ident, $af:expr, $c1:ty, $c2:ty, $c3:ty, $c4:ty) => {
-        channels!($TIMX, $af, $c1);
-
-        impl Pins<$TIMX> for $c2 {
-            type Channels = Pwm<$TIMX, C2>;
-
-            fn setup(&self) {
-                self.set_alt_mode($af);
-            }
-        }
-
-        impl Pins<$TIMX> for $c3 {
-            type Channels = Pwm<$TIMX, C3>;
-
-            fn setup(&self) {
-                self.set_alt_mode($af);
-            }
-        }
-
-        impl Pins<$TIMX> for $c4 {
-            type Channels = Pwm<$TIMX, C4>;
-
-            fn setup(&self) {
-                self.set_alt_mode($af);
-            }
-        }
+}
 
-        impl Pins<$TIMX> for ($c1, $c2) {
-            type Channels = (Pwm<$TIMX, C1>, Pwm<$TIMX, C2>);
+macro_rules! channels {
+    ($($TIMX:ident: [$($C:ident),+],)+) => {
+        $(
+            $(
+                channel!($TIMX, $C);
+            )+
+        )+
+    };
+}
 
-            fn setup(&self) {
-                self.0.set_alt_mode($af);
-                self.1.set_alt_mode($af);
-            }
-        }
+macro_rules! pins {
+    ($($TIMX:ident: $af:expr => [$($PIN:ty: $C:ident,)+],)+) => {
+        $(
+            $(
+                impl Pin<$TIMX> for $PIN {
+                    type Channel = $C;
 
-        impl Pins<$TIMX> for ($c1, $c2, $c3, $c4) {
-            type Channels = (
-                Pwm<$TIMX, C1>,
-                Pwm<$TIMX, C2>,
-                Pwm<$TIMX, C3>,
-                Pwm<$TIMX, C4>,
-            );
-
-            fn setup(&self) {
-                self.0.set_alt_mode($af);
-                self.1.set_alt_mode($af);
-                self.2.set_alt_mode($af);
-                self.3.set_alt_mode($af);
-            }
-        }
+                    fn setup(&self) {
+                        self.set_alt_mode($af);
+                    }
+                }
 
-        impl hal::PwmPin for Pwm<$TIMX, C2> {
-            type Duty = u16;
+                impl Pins<$TIMX> for $PIN {
+                    type Channels = Pwm<$TIMX, $C>;
 
-            fn disable(&mut self) {
-                unsafe {
-                    (*$TIMX::ptr()).ccer.modify(|_, w| w.cc2e().clear_bit());
+                    fn setup(&self) {
+                        Pin::<$TIMX>::setup(self);
+                    }
                 }
-            }
+            )+
+        )+
+    };
+}
 
-            fn enable(&mut self) {
-                unsafe {
-                    let tim = &*$TIMX::ptr();
-                    tim.ccmr1_output()
-                        .modify(|_, w| w.oc2pe().set_bit().oc2m().bits(6));
-                    tim.ccer.modify(|_, w| w.cc2e().set_bit());
+macro_rules! timers {
+    ($($TIMX:ident,)+) => {
+        $(
+            impl PwmExt for $TIMX {
+                fn pwm<PINS, T>(
+                    self,
+                    pins: PINS,
+                    frequency: T,
+                    rcc: &mut Rcc,
+                ) -> Result<PINS::Channels, Error>
+                where
+                    PINS: Pins<Self>,
+                    T: Into<Hertz>,
+                {
+                    let timer = self.pwm_timer(frequency, rcc)?;
+                    Ok(timer.channels(pins).1)
                 }
-            }
-
-            fn get_duty(&self) -> u16 {
-                unsafe { (*$TIMX::ptr()).ccr2().read().ccr().bits() }
-            }
-
-            fn get_max_duty(&self) -> u16 {
-                unsafe { (*$TIMX::ptr()).arr.read().arr().bits() }
-            }
 
-            fn set_duty(&mut self, duty: u16) {
-                unsafe { (*$TIMX::ptr()).ccr2().write(|w| w.ccr().bits(duty)) }
+                fn pwm_timer<T>(self, frequency: T, rcc: &mut Rcc) -> Result<PwmTimer<Self>, Error>
+                where
+                    T: Into<Hertz>,
+                {
+                    PwmTimer::<$TIMX>::new(self, frequency, rcc)
+                }
             }
-        }
-
-        impl hal::PwmPin for Pwm<$TIMX, C3> {
-            type Duty = u16;
 
-            fn disable(&mut self) {
-                unsafe {
-                    (*$TIMX::ptr()).ccer.modify(|_, w| w.cc3e().clear_bit());
+            impl PwmTimer<$TIMX> {
+                fn new<T>(tim: $TIMX, frequency: T, rcc: &mut Rcc) -> Result<Self, Error>
+                where
+                    T: Into<Hertz>,
+                {
+                    let frequency = frequency.into();
+                    let clk = $TIMX::timer_clock(&rcc.clocks);
+                    let (psc, arr) = psc_arr(clk, frequency, Alignment::Edge)?;
+
+                    $TIMX::enable(rcc);
+                    $TIMX::reset(rcc);
+                    // the outputs keep running in sleep mode
+                    rcc::set_sleep_clock::<$TIMX>(true);
+
+                    let mut timer = PwmTimer {
+                        tim,
+                        clk,
+                        frequency,
+                        alignment: Alignment::Edge,
+                        _state: PhantomData,
+                    };
+                    timer.write_period(psc, arr);
+                    timer.tim.cr1.modify(|_, w| w.cen().set_bit());
+                    Ok(timer)
                 }
-            }
 
-            fn enable(&mut self) {
-                unsafe {
-                    let tim = &*$TIMX::ptr();
-                    tim.ccmr2_output()
-                        .modify(|_, w| w.oc3pe().set_bit().oc3m().bits(6));
-                    tim.ccer.modify(|_, w| w.cc3e().set_bit());
+                /// Configures `pins` for PWM output and returns their channels
+                pub fn channels<PINS>(
+                    self,
+                    pins: PINS,
+                ) -> (PwmTimer<$TIMX, Split<PINS::Channels>>, PINS::Channels)
+                where
+                    PINS: Pins<$TIMX>,
+                {
+                    pins.setup();
+                    let timer = PwmTimer {
+                        tim: self.tim,
+                        clk: self.clk,
+                        frequency: self.frequency,
+                        alignment: self.alignment,
+                        _state: PhantomData,
+                    };
+                    (timer, sealed::Channels::new())
                 }
-            }
 
-            fn get_duty(&self) -> u16 {
-                unsafe { (*$TIMX::ptr()).ccr3().read().ccr().bits() }
+                /// Stops the timer and releases it
+                pub fn release(self) -> $TIMX {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    self.tim
+                }
             }
 
-            fn get_max_duty(&self) -> u16 {
-                unsafe { (*$TIMX::ptr()).arr.read().arr().bits() }
+            impl<CHANNELS> PwmTimer<$TIMX, Split<CHANNELS>> {
+                /// Stops the timer and releases it, taking back its channels
+                pub fn release(self, _channels: CHANNELS) -> $TIMX {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    self.tim
+                }
             }
 
-            fn set_duty(&mut self, duty: u16) {
-                unsafe { (*$TIMX::ptr()).ccr3().write(|w| w.ccr().bits(duty)) }
-            }
-        }
+            impl<STATE> PwmTimer<$TIMX, STATE> {
+                /// Changes the PWM frequency
+                ///
+                /// The duty cycles are kept in timer ticks, so they have to be scaled to
+                /// the new maximum duty by the caller.
+                pub fn set_frequency<T>(&mut self, frequency: T) -> Result<(), Error>
+                where
+                    T: Into<Hertz>,
+                {
+                    let frequency = frequency.into();
+                    let (psc, arr) = psc_arr(self.clk, frequency, self.alignment)?;
+                    self.write_period(psc, arr);
+                    self.frequency = frequency;
+                    Ok(())
+                }
 
-        impl hal::PwmPin for Pwm<$TIMX, C4> {
-            type Duty = u16;
+                /// Returns the configured PWM frequency
+                pub fn frequency(&self) -> Hertz {
+                    self.frequency
+                }
 
-            fn disable(&mut self) {
-                unsafe {
-                    (*$TIMX::ptr()).ccer.modify(|_, w| w.cc4e().clear_bit());
+                /// Keeps the PWM frequency after the timer clock changed to `clocks`
+                ///
+                /// Only the prescaler is changed when it scales exactly, so the duty
+                /// cycles stay valid. Otherwise the period is split again, and the
+                /// duty cycles have to be scaled to the new maximum duty by the caller.
+                pub fn set_clocks(&mut self, clocks: &Clocks) -> Result<(), Error> {
+                    let clk = $TIMX::timer_clock(clocks);
+                    let old = u64::from(self.clk.0);
+                    let new = u64::from(clk.0);
+                    let psc = u64::from(self.tim.psc.read().psc().bits()) + 1;
+
+                    let scaled = psc * new / old;
+                    let (psc, arr) =
+                        if (psc * new).is_multiple_of(old) && (1..=1 << 16).contains(&scaled) {
+                            ((scaled - 1) as u16, self.get_max_duty())
+                        } else {
+                            psc_arr(clk, self.frequency, self.alignment)?
+                        };
+                    self.clk = clk;
+                    self.write_period(psc, arr);
+                    Ok(())
                 }
-            }
 
-            fn enable(&mut self) {
-                unsafe {
-                    let tim = &*$TIMX::ptr();
-                    tim.ccmr2_output()
-                        .modify(|_, w| w.oc4pe().set_bit().oc4m().bits(6));
-                    tim.ccer.modify(|_, w| w.cc4e().set_bit());
+                /// Returns the duty cycle value that corresponds to a full period
+                pub fn get_max_duty(&self) -> u16 {
+                    self.tim.arr.read().arr().bits()
                 }
-            }
 
-            fn get_duty(&self) -> u16 {
-                unsafe { (*$TIMX::ptr()).ccr4().read().ccr().bits() }
-            }
+                /// Sets whether the timer clock runs in sleep mode, see [`rcc::set_sleep_clock`]
+                pub fn set_sleep_clock(&mut self, enabled: bool) {
+                    rcc::set_sleep_clock::<$TIMX>(enabled);
+                }
 
-            fn get_max_duty(&self) -> u16 {
-                unsafe { (*$TIMX::ptr()).arr.read().arr().bits() }
+                fn write_period(&mut self, psc: u16, arr: u16) {
+                    self.tim.psc.write(|w| w.psc().bits(psc));
+                    #[allow(unused_unsafe)]
+                    self.tim.arr.write(|w| unsafe { w.arr().bits(arr) });
+                    // load the new prescaler right away
+                    self.tim.egr.write(|w| w.ug().set_bit());
+                }
             }
+        )+
+    }
+}
 
-            fn set_duty(&mut self, duty: u16) {
-                unsafe { (*$TIMX::ptr()).ccr4().write(|w| w.ccr().bits(duty)) }
+macro_rules! alignment {
+    ($($TIMX:ident,)+) => {
+        $(
+            impl<STATE> PwmTimer<$TIMX, STATE> {
+                /// Switches between edge-aligned and center-aligned counting
+                ///
+                /// The PSC and ARR registers are recomputed so the PWM frequency stays
+                /// the same.
+                pub fn set_alignment(&mut self, alignment: Alignment) -> Result<(), Error> {
+                    let (psc, arr) = psc_arr(self.clk, self.frequency, alignment)?;
+                    // CMS can only be changed while the counter is disabled
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    #[allow(unused_unsafe)]
+                    self.tim
+                        .cr1
+                        .modify(|_, w| unsafe { w.cms().bits(alignment as u8) });
+                    self.write_period(psc, arr);
+                    self.tim.cr1.modify(|_, w| w.cen().set_bit());
+                    self.alignment = alignment;
+                    Ok(())
+                }
             }
-        }
-    };
+        )+
+    }
 }
 
-macro_rules! timers {
-    ($($TIMX:ident: ($apb_clk:ident, $apbXenr:ident, $apbXrstr:ident, $timX:ident, $timXen:ident, $timXrst:ident),)+) => {
+macro_rules! sync {
+    ($($TIMX:ident,)+) => {
         $(
-            impl PwmExt for $TIMX {
-                fn pwm<PINS, T>(
-                    self,
-                    pins: PINS,
-                    freq: T,
-                    rcc: &mut Rcc,
-                ) -> PINS::Channels
+            impl<STATE> PwmTimer<$TIMX, STATE> {
+                /// Selects the signal the timer drives on its trigger output (TRGO)
+                pub fn set_master_mode(&mut self, mode: MasterMode) {
+                    #[allow(unused_unsafe)]
+                    self.tim.cr2.modify(|_, w| unsafe { w.mms().bits(mode as u8) });
+                }
+
+                /// Synchronizes the timer to the trigger output of `MASTER`
+                ///
+                /// Use [`SlaveMode::Reset`] with a master in [`MasterMode::Update`] to
+                /// keep several PWM timers in phase.
+                pub fn set_slave_mode<MASTER>(&mut self, mode: SlaveMode)
                 where
-                    PINS: Pins<Self>,
-                    T: Into<Hertz>,
+                    $TIMX: InternalTrigger<MASTER>,
                 {
-                    $timX(self, pins, freq.into(), rcc)
+                    let ts = <$TIMX as InternalTrigger<MASTER>>::TS;
+                    #[allow(unused_unsafe)]
+                    self.tim
+                        .smcr
+                        .modify(|_, w| unsafe { w.ts().bits(ts).sms().bits(mode as u8) });
                 }
             }
-
-            fn $timX<PINS>(
-                tim: $TIMX,
-                pins: PINS,
-                freq: Hertz,
-                rcc: &mut Rcc,
-            ) -> PINS::Channels
-            where
-                PINS: Pins<$TIMX>,
-            {
-                pins.setup();
-                rcc.rb.$apbXenr.modify(|_, w| w.$timXen().set_bit());
-                rcc.rb.$apbXrstr.modify(|_, w| w.$timXrst().set_bit());
-                rcc.rb.$apbXrstr.modify(|_, w| w.$timXrst().clear_bit());
-
-                let clk = rcc.clocks.$apb_clk().0;
-                let freq = freq.0;
-                let ticks = clk / freq;
-                let psc = u16((ticks - 1) / (1 << 16)).unwrap();
-                let arr = u16(ticks / u32(psc + 1)).unwrap();
-                tim.psc.write(|w| w.psc().bits(psc));
-                #[allow(unused_unsafe)]
-                tim.arr.write(|w| unsafe { w.arr().bits(arr) });
-                tim.cr1.modify(|_, w| w.cen().set_bit());
-                unsafe { mem::MaybeUninit::uninit().assume_init() }
-            }
         )+
     }
 }
 
-channels!(
+channels! {
+    TIM2: [C1, C2, C3, C4],
+    TIM3: [C1, C2, C3, C4],
+    TIM4: [C1, C2, C3, C4],
+    TIM9: [C1, C2],
+    TIM10: [C1],
+    TIM11: [C1],
+}
+
+pins! {
+    TIM2: AltMode::TIM2 => [
+        gpioa::PA0<Input<Floating>>: C1,
+        gpioa::PA1<Input<Floating>>: C2,
+        gpioa::PA2<Input<Floating>>: C3,
+        gpioa::PA3<Input<Floating>>: C4,
+        gpioa::PA5<Input<Floating>>: C1,
+        gpioa::PA15<Input<Floating>>: C1,
+        gpiob::PB3<Input<Floating>>: C2,
+        gpiob::PB10<Input<Floating>>: C3,
+        gpiob::PB11<Input<Floating>>: C4,
+    ],
+    TIM3: AltMode::TIM3_5 => [
+        gpioa::PA6<Input<Floating>>: C1,
+        gpioa::PA7<Input<Floating>>: C2,
+        gpiob::PB0<Input<Floating>>: C3,
+        gpiob::PB1<Input<Floating>>: C4,
+        gpiob::PB4<Input<Floating>>: C1,
+        gpiob::PB5<Input<Floating>>: C2,
+        gpioc::PC6<Input<Floating>>: C1,
+        gpioc::PC7<Input<Floating>>: C2,
+        gpioc::PC8<Input<Floating>>: C3,
+        gpioc::PC9<Input<Floating>>: C4,
+    ],
+    TIM4: AltMode::TIM3_5 => [
+        gpiob::PB6<Input<Floating>>: C1,
+        gpiob::PB7<Input<Floating>>: C2,
+        gpiob::PB8<Input<Floating>>: C3,
+        gpiob::PB9<Input<Floating>>: C4,
+        gpiod::PD12<Input<Floating>>: C1,
+        gpiod::PD13<Input<Floating>>: C2,
+        gpiod::PD14<Input<Floating>>: C3,
+        gpiod::PD15<Input<Floating>>: C4,
+    ],
+    TIM9: AltMode::TIM9_11 => [
+        gpioa::PA2<Input<Floating>>: C1,
+        gpioa::PA3<Input<Floating>>: C2,
+        gpiob::PB13<Input<Floating>>: C1,
+        gpiob::PB14<Input<Floating>>: C2,
+        gpiod::PD0<Input<Floating>>: C1,
+        gpiod::PD7<Input<Floating>>: C2,
+    ],
+    TIM10: AltMode::TIM9_11 => [
+        gpioa::PA6<Input<Floating>>: C1,
+        gpiob::PB8<Input<Floating>>: C1,
+        gpiob::PB12<Input<Floating>>: C1,
+    ],
+    TIM11: AltMode::TIM9_11 => [
+        gpioa::PA7<Input<Floating>>: C1,
+        gpiob::PB9<Input<Floating>>: C1,
+        gpiob::PB15<Input<Floating>>: C1,
+    ],
+}
+
+#[cfg(any(feature = "stm32l151", feature = "stm32l152", feature = "stm32l162"))]
+pins! {
+    TIM2: AltMode::TIM2 => [
+        gpioe::PE9<Input<Floating>>: C1,
+        gpioe::PE10<Input<Floating>>: C2,
+        gpioe::PE11<Input<Floating>>: C3,
+        gpioe::PE12<Input<Floating>>: C4,
+    ],
+    TIM3: AltMode::TIM3_5 => [
+        gpioe::PE3<Input<Floating>>: C1,
+        gpioe::PE4<Input<Floating>>: C2,
+    ],
+    TIM9: AltMode::TIM9_11 => [
+        gpioe::PE5<Input<Floating>>: C1,
+        gpioe::PE6<Input<Floating>>: C2,
+    ],
+    TIM10: AltMode::TIM9_11 => [
+        gpioe::PE0<Input<Floating>>: C1,
+    ],
+    TIM11: AltMode::TIM9_11 => [
+        gpioe::PE1<Input<Floating>>: C1,
+    ],
+}
+
+timers! {
     TIM2,
-    AltMode::TIM2,
-    PA0<Input<Floating>>,
-    PA1<Input<Floating>>,
-    PA2<Input<Floating>>,
-    PA3<Input<Floating>>
-);
-channels!(
     TIM3,
-    AltMode::TIM3_5,
-    PA6<Input<Floating>>,
-    PA7<Input<Floating>>,
-    PB0<Input<Floating>>,
-    PB1<Input<Floating>>
-);
-channels!(
     TIM4,
-    AltMode::TIM3_5,
-    PB6<Input<Floating>>,
-    PB7<Input<Floating>>,
-    PB8<Input<Floating>>,
-    PB9<Input<Floating>>
-);
-channels!(
-    TIM5,
-    AltMode::TIM3_5,
-    PA0<Input<Floating>>,
-    PA1<Input<Floating>>,
-    PA2<Input<Floating>>,
-    PA3<Input<Floating>>
-);
-channels!(TIM10, AltMode::TIM9_11, PA6<Input<Floating>>);
-channels!(TIM11, AltMode::TIM9_11, PA7<Input<Floating>>);
+    TIM9,
+    TIM10,
+    TIM11,
+}
 
-timers! {
-    TIM2: (apb1_clk, apb1enr, apb1rstr, tim2, tim2en, tim2rst),
-    TIM3: (apb1_clk, apb1enr, apb1rstr, tim3, tim3en, tim3rst),
-    TIM4: (apb1_clk, apb1enr, apb1rstr, tim4, tim4en, tim4rst),
-    TIM5: (apb1_clk, apb1enr, apb1rstr, tim5, tim5en, tim5rst),
-    TIM10: (apb2_clk, apb2enr, apb2rstr, tim10, tim10en, tm10rst),
-    TIM11: (apb2_clk, apb2enr, apb2rstr, tim11, tim11en, tm11rst),
+alignment! {
+    TIM2,
+    TIM3,
+    TIM4,
+}
+
+sync! {
+    TIM2,
+    TIM3,
+    TIM4,
+    TIM9,
+}
+
+#[cfg(feature = "_cat3plus")]
+mod tim5 {
+    use super::*;
+
+    channels! {
+        TIM5: [C1, C2, C3, C4],
+    }
+
+    pins! {
+        TIM5: AltMode::TIM3_5 => [
+            gpioa::PA0<Input<Floating>>: C1,
+            gpioa::PA1<Input<Floating>>: C2,
+            gpioa::PA2<Input<Floating>>: C3,
+            gpioa::PA3<Input<Floating>>: C4,
+        ],
+    }
+
+    timers! {
+        TIM5,
+    }
+
+    alignment! {
+        TIM5,
+    }
+
+    sync! {
+        TIM5,
+    }
 }
@@ -1,46 +1,112 @@
 //! Quadrature Encoder Interface
-use crate::gpio::gpioa::{PA0, PA1, PA6, PA7};
-use crate::gpio::gpiob::{PB6, PB7};
-use crate::gpio::{AltMode, Floating, Input};
+//!
+//! The encoder pins are the channel 1 and 2 pins of the timer, see
+//! [`crate::pwm::Pin`] for the available mappings.
+use crate::bb;
 use crate::hal::{self, Direction};
-use crate::rcc::Rcc;
-use crate::stm32::{TIM2, TIM3, TIM4, TIM5};
+use crate::pwm::{ccer, Pin, C1, C2};
+use crate::rcc::{self, Enable, Rcc, Reset};
+#[cfg(feature = "_cat3plus")]
+use crate::stm32::TIM5;
+use crate::stm32::{TIM2, TIM3, TIM4, TIM9};
 
 pub trait Pins<TIM> {
     fn setup(&self);
 }
 
-impl Pins<TIM2> for (PA0<Input<Floating>>, PA1<Input<Floating>>) {
+impl<TIM, P1, P2> Pins<TIM> for (P1, P2)
+where
+    P1: Pin<TIM, Channel = C1>,
+    P2: Pin<TIM, Channel = C2>,
+{
     fn setup(&self) {
-        self.0.set_alt_mode(AltMode::TIM2);
-        self.1.set_alt_mode(AltMode::TIM2);
+        self.0.setup();
+        self.1.setup();
     }
 }
 
-impl Pins<TIM3> for (PA6<Input<Floating>>, PA7<Input<Floating>>) {
-    fn setup(&self) {
-        self.0.set_alt_mode(AltMode::TIM3_5);
-        self.1.set_alt_mode(AltMode::TIM3_5);
-    }
+/// Inputs the counter counts on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Count on TI1 edges, depending on the TI2 level
+    TI1 = 0b001,
+    /// Count on TI2 edges, depending on the TI1 level
+    TI2 = 0b010,
+    /// Count on both TI1 and TI2 edges
+    TI1AndTI2 = 0b011,
 }
 
-impl Pins<TIM4> for (PB6<Input<Floating>>, PB7<Input<Floating>>) {
-    fn setup(&self) {
-        self.0.set_alt_mode(AltMode::TIM3_5);
-        self.1.set_alt_mode(AltMode::TIM3_5);
+/// Polarity of an encoder input
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Polarity {
+    Normal,
+    /// The input is inverted, which reverses the counting direction
+    Inverted,
+}
+
+/// What an index pulse does to the position
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexMode {
+    /// Resets the position to zero
+    Reset,
+    /// Keeps the position, see [`Qei::latched_position`]
+    Latch,
+}
+
+/// Encoder configuration
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    mode: Mode,
+    filters: (u8, u8),
+    polarities: (Polarity, Polarity),
+    auto_reload: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            mode: Mode::TI1AndTI2,
+            filters: (0, 0),
+            polarities: (Polarity::Normal, Polarity::Normal),
+            auto_reload: u32::MAX,
+        }
     }
 }
 
-impl Pins<TIM5> for (PA0<Input<Floating>>, PA1<Input<Floating>>) {
-    fn setup(&self) {
-        self.0.set_alt_mode(AltMode::TIM3_5);
-        self.1.set_alt_mode(AltMode::TIM3_5);
+impl Config {
+    /// Selects the inputs the counter counts on
+    pub fn with_mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets the input filters of TI1 and TI2 (ICxF, 0 to 15)
+    pub fn with_filters(mut self, ti1: u8, ti2: u8) -> Self {
+        self.filters = (ti1 & 0xf, ti2 & 0xf);
+        self
+    }
+
+    /// Sets the polarities of TI1 and TI2
+    pub fn with_polarities(mut self, ti1: Polarity, ti2: Polarity) -> Self {
+        self.polarities = (ti1, ti2);
+        self
+    }
+
+    /// Sets the auto-reload, the counter wraps after `auto_reload + 1` counts
+    ///
+    /// The value is clamped to the counter width of the timer.
+    pub fn with_auto_reload(mut self, auto_reload: u32) -> Self {
+        self.auto_reload = auto_reload;
+        self
     }
 }
 
 pub struct Qei<TIM, PINS> {
     tim: TIM,
     pins: PINS,
+    last: u32,
+    position: i64,
+    latched: Option<i64>,
 }
 
 pub trait QeiExt<TIM, PINS>
@@ -48,63 +114,115 @@ where
     PINS: Pins<TIM>,
 {
     fn qei(self, pins: PINS, rcc: &mut Rcc) -> Qei<TIM, PINS>;
+
+    fn qei_with_config(self, pins: PINS, config: Config, rcc: &mut Rcc) -> Qei<TIM, PINS>;
 }
 
 macro_rules! hal {
-    ($($TIMX:ident: ($timX:ident, $timXen:ident, $timXrst:ident),)+) => {
+    ($($TIMX:ident: ($timX:ident, $Count:ty),)+) => {
         $(
             impl<PINS> Qei<$TIMX, PINS> where PINS: Pins<$TIMX> {
-                fn $timX(tim: $TIMX, pins: PINS, rcc: &mut Rcc) -> Self {
+                fn $timX(tim: $TIMX, pins: PINS, config: Config, rcc: &mut Rcc) -> Self {
                     pins.setup();
                     // enable and reset peripheral to a clean slate state
-                    rcc.rb.apb1enr.modify(|_, w| w.$timXen().set_bit());
-                    rcc.rb.apb1rstr.modify(|_, w| w.$timXrst().set_bit());
-                    rcc.rb.apb1rstr.modify(|_, w| w.$timXrst().clear_bit());
+                    $TIMX::enable(rcc);
+                    $TIMX::reset(rcc);
+                    // keep counting in sleep mode
+                    rcc::set_sleep_clock::<$TIMX>(true);
 
-                    // Configure TxC1 and TxC2 as captures
-                    tim.ccmr1_output().write(|w| unsafe {
-                        w.cc1s().bits(0b01).cc2s().set_bit()
+                    // Configure TxC1 and TxC2 as captures on TI1 and TI2, with filters
+                    let (f1, f2) = config.filters;
+                    tim.ccmr1_input().write(|w| unsafe {
+                        w.bits(0b01 | u32::from(f1) << 4 | 0b01 << 8 | u32::from(f2) << 12)
                     });
 
-                    // Enable and configure to capture on rising edge
-                    tim.ccer.write(|w| {
-                        w.cc1e()
-                            .set_bit()
-                            .cc2e()
-                            .set_bit()
-                            .cc1p()
-                            .clear_bit()
-                            .cc2p()
-                            .clear_bit()
-                            .cc1np()
-                            .clear_bit()
-                            .cc2np()
-                            .clear_bit()
-                    });
+                    // Enable the captures, CCxP selects the input polarity
+                    let (p1, p2) = config.polarities;
+                    let ccer = ccer!($TIMX);
+                    bb::write(ccer, 1, p1 == Polarity::Inverted);
+                    bb::write(ccer, 5, p2 == Polarity::Inverted);
+                    bb::set(ccer, 0);
+                    bb::set(ccer, 4);
+
+                    tim.smcr.write(|w| unsafe { w.bits(config.mode as u32) });
+
+                    let auto_reload = config.auto_reload.min(<$Count>::MAX.into());
+                    tim.arr.write(|w| unsafe { w.bits(auto_reload) });
+                    tim.cr1.write(|w| w.cen().set_bit());
+
+                    Qei {
+                        tim,
+                        pins,
+                        last: 0,
+                        position: 0,
+                        latched: None,
+                    }
+                }
 
-                    // Encoder mode, count up/down on both TI1FP1 and TI2FP2
-                    tim.smcr.write(|w| w.sms().bits(0b011) );
+                /// Returns the position, extended from the counter to 64 bits
+                ///
+                /// Each call adds the counter change since the previous one, taking
+                /// the shorter way around the auto-reload. Overflows are not counted
+                /// in between: the encoder must not move by half the counter range
+                /// (`auto_reload / 2` steps) or more between two calls, otherwise
+                /// the position silently jumps by a full range. Poll it often enough
+                /// for the highest encoder speed, for instance from a periodic task.
+                pub fn position(&mut self) -> i64 {
+                    let count = self.tim.cnt.read().bits();
+                    let range = i64::from(self.tim.arr.read().bits()) + 1;
 
-                    tim.arr.write(|w| w.arr().bits(u16::MAX));
-                    tim.cr1.write(|w| w.cen().enabled());
+                    let mut delta = i64::from(count) - i64::from(self.last);
+                    if delta >= range / 2 {
+                        delta -= range;
+                    } else if delta < -range / 2 {
+                        delta += range;
+                    }
 
-                    Qei { tim, pins }
+                    self.last = count;
+                    self.position += delta;
+                    self.position
+                }
+
+                /// Handles an index (Z) pulse, call it from the EXTI handler of the index pin
+                pub fn index_pulse(&mut self, mode: IndexMode) {
+                    match mode {
+                        IndexMode::Reset => {
+                            self.tim.cnt.write(|w| unsafe { w.bits(0) });
+                            self.last = 0;
+                            self.position = 0;
+                        }
+                        IndexMode::Latch => {
+                            self.latched = Some(self.position());
+                        }
+                    }
+                }
+
+                /// Returns the position at the last latching index pulse
+                pub fn latched_position(&self) -> Option<i64> {
+                    self.latched
+                }
+
+                /// Sets whether the timer clock runs in sleep mode, see [`rcc::set_sleep_clock`]
+                pub fn set_sleep_clock(&mut self, enabled: bool) {
+                    rcc::set_sleep_clock::<$TIMX>(enabled);
                 }
 
                 pub fn release(self) -> ($TIMX, PINS) {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
                     (self.tim, self.pins)
                 }
             }
 
             impl<PINS> hal::Qei for Qei<$TIMX, PINS> {
-                type Count = u16;
+                type Count = $Count;
 
-                fn count(&self) -> u16 {
-                    self.tim.cnt.read().cnt().bits()
+                fn count(&self) -> $Count {
+                    self.tim.cnt.read().bits() as $Count
                 }
 
                 fn direction(&self) -> Direction {
-                    if self.tim.cr1.read().dir().bit_is_clear() {
+                    // DIR, which the PAC does not describe for TIM9
+                    if self.tim.cr1.read().bits() & (1 << 4) == 0 {
                         hal::Direction::Upcounting
                     } else {
                         hal::Direction::Downcounting
@@ -114,7 +232,16 @@ macro_rules! hal {
 
             impl<PINS> QeiExt<$TIMX, PINS> for $TIMX where PINS: Pins<$TIMX> {
                 fn qei(self, pins: PINS, rcc: &mut Rcc) -> Qei<$TIMX, PINS> {
-                    Qei::$timX(self, pins, rcc)
+                    Qei::$timX(self, pins, Config::default(), rcc)
+                }
+
+                fn qei_with_config(
+                    self,
+                    pins: PINS,
+                    config: Config,
+                    rcc: &mut Rcc,
+                ) -> Qei<$TIMX, PINS> {
+                    Qei::$timX(self, pins, config, rcc)
                 }
             }
         )+
@@ -122,8 +249,13 @@ macro_rules! hal {
 }
 
 hal! {
-    TIM2: (tim2, tim2en, tim2rst),
-    TIM3: (tim3, tim3en, tim3rst),
-    TIM4: (tim4, tim4en, tim4rst),
-    TIM5: (tim5, tim5en, tim5rst),
+    TIM2: (tim2, u16),
+    TIM3: (tim3, u16),
+    TIM4: (tim4, u16),
+    TIM9: (tim9, u16),
+}
+
+#[cfg(feature = "_cat3plus")]
+hal! {
+    TIM5: (tim5, u32),
 }
@@ -1,9 +1,109 @@
+mod enable;
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::bb;
 use crate::mco;
 use crate::mco::{MCODiv, MCOSel};
-use crate::stm32::RCC;
+use crate::pwr::Pwr;
+use crate::stm32::{FLASH, PWR, RCC, TIM10};
 use crate::time::{Hertz, U32Ext};
 
+pub use enable::{BusClock, BusTimerClock, Enable, LPEnable, RccBus, Reset, AHB, APB1, APB2};
+
+/// Clock configuration error
+#[derive(Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The LSE oscillator is not running
+    LseNotReady,
+    /// The system clock does not run from the oscillator to calibrate
+    WrongClockSource,
+    /// The system clock is above 32 MHz
+    SysClkTooHigh,
+    /// The PLL VCO is above 96 MHz
+    VcoTooHigh,
+    /// The HSE is above 32 MHz
+    HseTooHigh,
+    /// The HSE is in use but its frequency was not given
+    HseFrequencyUnknown,
+    /// A clock register holds a reserved value
+    ReservedSetting,
+    /// The PLL input is outside 2 to 24 MHz
+    PllInputOutOfRange,
+    /// No PLL setting produces the requested frequency
+    NoPllSolution,
+    /// The clock to measure gave no TIM10 capture within a counter period
+    MeasurementTimeout,
+}
+
+/// Core voltage range (PWR_CR VOS)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VoltageRange {
+    /// 1.8 V, up to 32 MHz
+    Range1 = 0b01,
+    /// 1.5 V, up to 16 MHz
+    Range2 = 0b10,
+    /// 1.2 V, up to 4.2 MHz
+    Range3 = 0b11,
+}
+
+impl VoltageRange {
+    /// Returns the lowest of ranges 1 and 2 that supports the clocks
+    ///
+    /// Range 3 is never selected, since it forbids flash and EEPROM programming.
+    const fn for_clocks(sys_clk: u32, vco: u32, hse: u32) -> Result<VoltageRange, Error> {
+        if sys_clk > 32_000_000 {
+            Err(Error::SysClkTooHigh)
+        } else if vco > 96_000_000 {
+            Err(Error::VcoTooHigh)
+        } else if hse > 32_000_000 {
+            Err(Error::HseTooHigh)
+        } else if sys_clk > 16_000_000 || vco > 48_000_000 || hse > 16_000_000 {
+            Ok(VoltageRange::Range1)
+        } else {
+            Ok(VoltageRange::Range2)
+        }
+    }
+
+    /// Highest HCLK frequency that runs without a flash wait state
+    const fn zero_wait_state_max(self) -> u32 {
+        match self {
+            VoltageRange::Range1 => 16_000_000,
+            VoltageRange::Range2 => 8_000_000,
+            VoltageRange::Range3 => 2_100_000,
+        }
+    }
+}
+
+fn set_voltage_range(pwr: &crate::stm32::pwr::RegisterBlock, range: VoltageRange) {
+    while pwr.csr.read().vosf().bit_is_set() {}
+    pwr.cr.modify(|_, w| unsafe { w.vos().bits(range as u8) });
+    while pwr.csr.read().vosf().bit_is_set() {}
+}
+
+/// Switches between 0 and 1 flash wait state, 64-bit access has to be on for 1
+fn set_wait_state(enabled: bool) {
+    let flash = unsafe { &(*FLASH::ptr()) };
+    if enabled {
+        flash.acr.modify(|_, w| w.acc64().set_bit());
+        while flash.acr.read().acc64().bit_is_clear() {}
+        flash.acr.modify(|_, w| w.latency().set_bit());
+        while flash.acr.read().latency().bit_is_clear() {}
+        flash.acr.modify(|_, w| w.prften().set_bit());
+    } else {
+        flash.acr.modify(|_, w| w.latency().clear_bit());
+        while flash.acr.read().latency().bit_is_set() {}
+        // the prefetch buffer has to be off before 64-bit access is
+        flash.acr.modify(|_, w| w.prften().clear_bit());
+        while flash.acr.read().prften().bit_is_set() {}
+        flash.acr.modify(|_, w| w.acc64().clear_bit());
+        while flash.acr.read().acc64().bit_is_set() {}
+    }
+}
+
 /// System clock mux source
+#[derive(Clone, Copy)]
 pub enum ClockSrc {
     MSI(MSIRange),
     PLL(PLLSource, PLLMul, PLLDiv),
@@ -46,6 +146,42 @@ pub enum PLLMul {
     Mul48 = 8,
 }
 
+impl PLLDiv {
+    const ALL: [PLLDiv; 3] = [PLLDiv::Div2, PLLDiv::Div3, PLLDiv::Div4];
+
+    const fn divisor(self) -> u32 {
+        self as u32 + 1
+    }
+}
+
+impl PLLMul {
+    const ALL: [PLLMul; 9] = [
+        PLLMul::Mul3,
+        PLLMul::Mul4,
+        PLLMul::Mul6,
+        PLLMul::Mul8,
+        PLLMul::Mul12,
+        PLLMul::Mul16,
+        PLLMul::Mul24,
+        PLLMul::Mul32,
+        PLLMul::Mul48,
+    ];
+
+    const fn factor(self) -> u32 {
+        match self {
+            PLLMul::Mul3 => 3,
+            PLLMul::Mul4 => 4,
+            PLLMul::Mul6 => 6,
+            PLLMul::Mul8 => 8,
+            PLLMul::Mul12 => 12,
+            PLLMul::Mul16 => 16,
+            PLLMul::Mul24 => 24,
+            PLLMul::Mul32 => 32,
+            PLLMul::Mul48 => 48,
+        }
+    }
+}
+
 /// AHB prescaler
 #[derive(Clone, Copy)]
 pub enum AHBPrescaler {
@@ -60,6 +196,23 @@ pub enum AHBPrescaler {
     Div512 = 0b1111,
 }
 
+impl AHBPrescaler {
+    /// Returns the power of two the clock is divided by, there is no /32
+    const fn shift(self) -> u32 {
+        match self {
+            AHBPrescaler::NotDivided => 0,
+            AHBPrescaler::Div2 => 1,
+            AHBPrescaler::Div4 => 2,
+            AHBPrescaler::Div8 => 3,
+            AHBPrescaler::Div16 => 4,
+            AHBPrescaler::Div64 => 6,
+            AHBPrescaler::Div128 => 7,
+            AHBPrescaler::Div256 => 8,
+            AHBPrescaler::Div512 => 9,
+        }
+    }
+}
+
 /// APB prescaler
 #[derive(Clone, Copy)]
 pub enum APBPrescaler {
@@ -70,6 +223,16 @@ pub enum APBPrescaler {
     Div16 = 0b111,
 }
 
+/// HSE clock mode
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HSEClockMode {
+    /// HSE oscillator driven by an external crystal or ceramic resonator
+    #[default]
+    Oscillator,
+    /// HSE oscillator bypassed, an external clock drives `OSC_IN`
+    Bypass,
+}
+
 /// PLL clock input source
 #[derive(Clone, Copy)]
 pub enum PLLSource {
@@ -77,8 +240,34 @@ pub enum PLLSource {
     HSE(Hertz),
 }
 
+impl PLLSource {
+    const fn freq(self) -> u32 {
+        match self {
+            PLLSource::HSI => HSI_FREQ,
+            PLLSource::HSE(freq) => freq.0,
+        }
+    }
+}
+
+/// PLL input frequency range
+const PLL_IN_MIN: u32 = 2_000_000;
+const PLL_IN_MAX: u32 = 24_000_000;
+/// VCO frequency USB needs, divided by 2 for the 48 MHz USB clock
+const PLL_VCO_USB: u32 = 96_000_000;
+
 /// HSI speed
 pub const HSI_FREQ: u32 = 16_000_000;
+/// Typical LSI frequency, see [`Rcc::measure_lsi`] for the actual one
+pub const LSI_FREQ: u32 = 37_000;
+const LSE_FREQ: u32 = 32_768;
+
+/// Set by [`css_nmi_handler`], taken by [`Rcc::css_recovered`]
+static CSS_EVENT: AtomicBool = AtomicBool::new(false);
+/// Last [`Rcc::measure_lsi`] result, for the clocks [`css_nmi_handler`] returns
+static LSI_CLK: AtomicU32 = AtomicU32::new(LSI_FREQ);
+
+/// Input cycles counted per calibration measurement, 8 per capture
+const CALIBRATION_CYCLES: u32 = 128;
 
 /// Current system clock source (read from hardware)
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -93,12 +282,79 @@ pub enum SysClkSource {
     PLL,
 }
 
+/// Reset flags of RCC_CSR, with the standby flags of PWR_CSR
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ResetReason {
+    /// Reset on entering a low-power mode with nRST_STDBY or nRST_STOP cleared
+    pub low_power: bool,
+    /// Window watchdog reset
+    pub window_watchdog: bool,
+    /// Independent watchdog reset
+    pub independent_watchdog: bool,
+    /// Software reset through `SCB::sys_reset`
+    pub software: bool,
+    /// Power-on or power-down reset
+    pub power_on: bool,
+    /// Reset from the NRST pin
+    pub pin: bool,
+    /// Option byte loader reset
+    pub option_byte: bool,
+    /// The device was in standby mode before
+    pub standby: bool,
+    /// A wakeup event occurred, from a wakeup pin or the RTC
+    pub wakeup: bool,
+}
+
+/// Main cause of a reset, see [`ResetReason::cause`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetCause {
+    StandbyWakeup,
+    IndependentWatchdog,
+    WindowWatchdog,
+    Software,
+    LowPower,
+    OptionByte,
+    PowerOn,
+    Pin,
+    Unknown,
+}
+
+impl ResetReason {
+    /// Returns the most specific cause
+    ///
+    /// A power-on reset also sets the pin flag, and the watchdog and software
+    /// resets drive the NRST pin too, so the pin is reported when no other flag is.
+    pub fn cause(&self) -> ResetCause {
+        if self.standby {
+            ResetCause::StandbyWakeup
+        } else if self.independent_watchdog {
+            ResetCause::IndependentWatchdog
+        } else if self.window_watchdog {
+            ResetCause::WindowWatchdog
+        } else if self.software {
+            ResetCause::Software
+        } else if self.low_power {
+            ResetCause::LowPower
+        } else if self.option_byte {
+            ResetCause::OptionByte
+        } else if self.power_on {
+            ResetCause::PowerOn
+        } else if self.pin {
+            ResetCause::Pin
+        } else {
+            ResetCause::Unknown
+        }
+    }
+}
+
 /// Clocks configutation
+#[derive(Clone, Copy)]
 pub struct Config {
     mux: ClockSrc,
     ahb_pre: AHBPrescaler,
     apb1_pre: APBPrescaler,
     apb2_pre: APBPrescaler,
+    hse_mode: HSEClockMode,
 }
 
 impl Default for Config {
@@ -108,6 +364,7 @@ impl Default for Config {
             ahb_pre: AHBPrescaler::NotDivided,
             apb1_pre: APBPrescaler::NotDivided,
             apb2_pre: APBPrescaler::NotDivided,
+            hse_mode: HSEClockMode::Oscillator,
         }
     }
 }
@@ -137,13 +394,21 @@ impl Config {
         self
     }
 
+    /// Selects whether the HSE runs from a crystal or from an external clock
+    ///
+    /// The mode is applied when the HSE is started, it is left as is while the
+    /// HSE runs.
+    #[inline]
+    pub fn hse_mode(mut self, mode: HSEClockMode) -> Self {
+        self.hse_mode = mode;
+        self
+    }
+
     #[inline]
     pub fn hsi() -> Config {
         Config {
             mux: ClockSrc::HSI,
-            ahb_pre: AHBPrescaler::NotDivided,
-            apb1_pre: APBPrescaler::NotDivided,
-            apb2_pre: APBPrescaler::NotDivided,
+            ..Config::default()
         }
     }
 
@@ -151,9 +416,7 @@ impl Config {
     pub fn msi(range: MSIRange) -> Config {
         Config {
             mux: ClockSrc::MSI(range),
-            ahb_pre: AHBPrescaler::NotDivided,
-            apb1_pre: APBPrescaler::NotDivided,
-            apb2_pre: APBPrescaler::NotDivided,
+            ..Config::default()
         }
     }
 
@@ -161,9 +424,7 @@ impl Config {
     pub fn pll(pll_src: PLLSource, pll_mul: PLLMul, pll_div: PLLDiv) -> Config {
         Config {
             mux: ClockSrc::PLL(pll_src, pll_mul, pll_div),
-            ahb_pre: AHBPrescaler::NotDivided,
-            apb1_pre: APBPrescaler::NotDivided,
-            apb2_pre: APBPrescaler::NotDivided,
+            ..Config::default()
         }
     }
 
@@ -174,170 +435,305 @@ impl Config {
     {
         Config {
             mux: ClockSrc::HSE(freq.into()),
-            ahb_pre: AHBPrescaler::NotDivided,
-            apb1_pre: APBPrescaler::NotDivided,
-            apb2_pre: APBPrescaler::NotDivided,
+            ..Config::default()
         }
     }
-}
-
-/// RCC peripheral#[derive(Clone)]
-pub struct Rcc {
-    pub clocks: Clocks,
-    pub(crate) rb: RCC,
-    config: Config,
-}
-
-impl core::ops::Deref for Rcc {
-    type Target = RCC;
 
-    #[inline(always)]
-    fn deref(&self) -> &Self::Target {
-        &self.rb
+    /// Runs the system clock at exactly `sys_clk` from the PLL fed by `source`
+    ///
+    /// The setting with the lowest VCO frequency is picked, so that the lowest
+    /// voltage range is used: a VCO up to 48 MHz runs in range 2, up to 96 MHz in
+    /// range 1. Use [`Config::sysclk_with_usb`] when USB is needed.
+    pub fn sysclk<T>(sys_clk: T, source: PLLSource) -> Result<Config, Error>
+    where
+        T: Into<Hertz>,
+    {
+        Self::solve_pll(sys_clk.into().0, source, false)
     }
-}
 
-impl Rcc {
-    #[inline]
-    pub fn freeze(self, cfgr: Config) -> Rcc {
-        let (sys_clk, sw_bits) = match cfgr.mux {
-            ClockSrc::MSI(range) => {
-                let range = range as u8;
-                // Set MSI range
-                self.rb
-                    .icscr
-                    .modify(|_, w| unsafe { w.msirange().bits(range) });
+    /// Like [`Config::sysclk`], keeping the VCO at 96 MHz for the 48 MHz USB clock
+    pub fn sysclk_with_usb<T>(sys_clk: T, source: PLLSource) -> Result<Config, Error>
+    where
+        T: Into<Hertz>,
+    {
+        Self::solve_pll(sys_clk.into().0, source, true)
+    }
 
-                // Enable MSI
-                self.rb.cr.modify(|_, w| w.msion().set_bit());
-                while self.rb.cr.read().msirdy().bit_is_clear() {}
+    fn solve_pll(sys_clk: u32, source: PLLSource, usb: bool) -> Result<Config, Error> {
+        let input = source.freq();
+        if !(PLL_IN_MIN..=PLL_IN_MAX).contains(&input) {
+            return Err(Error::PllInputOutOfRange);
+        }
+        if sys_clk > 32_000_000 {
+            return Err(Error::SysClkTooHigh);
+        }
 
-                let freq = 32_768 * (1 << (range + 1));
-                (freq, 0)
+        let mut best: Option<(u32, PLLMul, PLLDiv)> = None;
+        for mul in PLLMul::ALL {
+            let vco = input * mul.factor();
+            if vco > PLL_VCO_USB || (usb && vco != PLL_VCO_USB) {
+                continue;
             }
-            ClockSrc::HSI => {
-                // Enable HSI
-                self.rb.cr.modify(|_, w| w.hsion().set_bit());
-                while self.rb.cr.read().hsirdy().bit_is_clear() {}
-
-                (HSI_FREQ, 1)
+            for div in PLLDiv::ALL {
+                let exact = vco.is_multiple_of(div.divisor()) && vco / div.divisor() == sys_clk;
+                if exact && best.is_none_or(|(best_vco, _, _)| vco < best_vco) {
+                    best = Some((vco, mul, div));
+                }
             }
-            ClockSrc::HSE(freq) => {
-                // Enable HSE
-                self.rb.cr.modify(|_, w| w.hseon().set_bit());
-                while self.rb.cr.read().hserdy().bit_is_clear() {}
+        }
 
-                (freq.0, 2)
-            }
-            ClockSrc::PLL(src, mul, div) => {
-                let (src_bit, freq) = match src {
-                    PLLSource::HSE(freq) => {
-                        // Enable HSE
-                        self.rb.cr.modify(|_, w| w.hseon().set_bit());
-                        while self.rb.cr.read().hserdy().bit_is_clear() {}
-                        (true, freq.0)
-                    }
-                    PLLSource::HSI => {
-                        // Enable HSI
-                        self.rb.cr.modify(|_, w| w.hsion().set_bit());
-                        while self.rb.cr.read().hsirdy().bit_is_clear() {}
-                        (false, 15_998_976)
-                    }
-                };
+        best.map(|(_, mul, div)| Config::pll(source, mul, div))
+            .ok_or(Error::NoPllSolution)
+    }
 
-                // Disable PLL
-                self.rb.cr.modify(|_, w| w.pllon().clear_bit());
-                while self.rb.cr.read().pllrdy().bit_is_set() {}
+    /// Returns the clocks this configuration produces, without touching the hardware
+    ///
+    /// The LSI frequency is the typical one, see [`Rcc::measure_lsi`].
+    pub const fn clocks(&self) -> Result<Clocks, Error> {
+        let (sys_clk, _, _) = match self.sources() {
+            Ok(sources) => sources,
+            Err(e) => return Err(e),
+        };
 
-                let mul_bytes = mul as u8;
-                let div_bytes = div as u8;
-
-                let freq = match mul {
-                    PLLMul::Mul3 => freq * 3,
-                    PLLMul::Mul4 => freq * 4,
-                    PLLMul::Mul6 => freq * 6,
-                    PLLMul::Mul8 => freq * 8,
-                    PLLMul::Mul12 => freq * 12,
-                    PLLMul::Mul16 => freq * 16,
-                    PLLMul::Mul24 => freq * 24,
-                    PLLMul::Mul32 => freq * 32,
-                    PLLMul::Mul48 => freq * 48,
-                };
+        let ahb_clk = sys_clk >> self.ahb_pre.shift();
+        let (apb1_clk, apb1_tim_clk) = apb_clocks(ahb_clk, self.apb1_pre);
+        let (apb2_clk, apb2_tim_clk) = apb_clocks(ahb_clk, self.apb2_pre);
+
+        Ok(Clocks {
+            sys_clk: Hertz(sys_clk),
+            ahb_clk: Hertz(ahb_clk),
+            apb1_clk: Hertz(apb1_clk),
+            apb1_tim_clk: Hertz(apb1_tim_clk),
+            apb2_clk: Hertz(apb2_clk),
+            apb2_tim_clk: Hertz(apb2_tim_clk),
+            lsi_clk: Hertz(LSI_FREQ),
+        })
+    }
 
-                let freq = match div {
-                    PLLDiv::Div2 => freq / 2,
-                    PLLDiv::Div3 => freq / 3,
-                    PLLDiv::Div4 => freq / 4,
+    /// Returns the voltage range this configuration runs in
+    pub const fn voltage_range(&self) -> Result<VoltageRange, Error> {
+        match self.sources() {
+            Ok((sys_clk, vco, hse)) => VoltageRange::for_clocks(sys_clk, vco, hse),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Decodes the configuration the hardware currently runs, `hse` is the HSE
+    /// frequency if it is in use
+    fn from_registers(
+        rb: &crate::stm32::rcc::RegisterBlock,
+        hse: Option<Hertz>,
+    ) -> Result<Config, Error> {
+        let cfgr = rb.cfgr.read();
+        let hse = || hse.ok_or(Error::HseFrequencyUnknown);
+
+        let mux = match cfgr.sws().bits() {
+            0b00 => {
+                let range = match rb.icscr.read().msirange().bits() {
+                    0 => MSIRange::Range0,
+                    1 => MSIRange::Range1,
+                    2 => MSIRange::Range2,
+                    3 => MSIRange::Range3,
+                    4 => MSIRange::Range4,
+                    5 => MSIRange::Range5,
+                    6 => MSIRange::Range6,
+                    _ => return Err(Error::ReservedSetting),
+                };
+                ClockSrc::MSI(range)
+            }
+            0b01 => ClockSrc::HSI,
+            0b10 => ClockSrc::HSE(hse()?),
+            _ => {
+                let src = if cfgr.pllsrc().bit_is_clear() {
+                    PLLSource::HSI
+                } else {
+                    PLLSource::HSE(hse()?)
                 };
-                assert!(freq <= 24.mhz().0);
+                let mul = *PLLMul::ALL
+                    .get(usize::from(cfgr.pllmul().bits()))
+                    .ok_or(Error::ReservedSetting)?;
+                let div = match cfgr.plldiv().bits() {
+                    0b01 => PLLDiv::Div2,
+                    0b10 => PLLDiv::Div3,
+                    0b11 => PLLDiv::Div4,
+                    _ => return Err(Error::ReservedSetting),
+                };
+                ClockSrc::PLL(src, mul, div)
+            }
+        };
 
-                self.rb.cfgr.modify(|_, w| unsafe {
-                    w.pllmul()
-                        .bits(mul_bytes)
-                        .plldiv()
-                        .bits(div_bytes)
-                        .pllsrc()
-                        .bit(src_bit)
-                });
+        let ahb_pre = match cfgr.hpre().bits() {
+            0b1000 => AHBPrescaler::Div2,
+            0b1001 => AHBPrescaler::Div4,
+            0b1010 => AHBPrescaler::Div8,
+            0b1011 => AHBPrescaler::Div16,
+            0b1100 => AHBPrescaler::Div64,
+            0b1101 => AHBPrescaler::Div128,
+            0b1110 => AHBPrescaler::Div256,
+            0b1111 => AHBPrescaler::Div512,
+            _ => AHBPrescaler::NotDivided,
+        };
+        let apb_pre = |bits: u8| match bits {
+            0b100 => APBPrescaler::Div2,
+            0b101 => APBPrescaler::Div4,
+            0b110 => APBPrescaler::Div8,
+            0b111 => APBPrescaler::Div16,
+            _ => APBPrescaler::NotDivided,
+        };
 
-                // Enable PLL
-                self.rb.cr.modify(|_, w| w.pllon().set_bit());
-                while self.rb.cr.read().pllrdy().bit_is_clear() {}
+        Ok(Config {
+            mux,
+            ahb_pre,
+            apb1_pre: apb_pre(cfgr.ppre1().bits()),
+            apb2_pre: apb_pre(cfgr.ppre2().bits()),
+            hse_mode: if rb.cr.read().hsebyp().bit_is_set() {
+                HSEClockMode::Bypass
+            } else {
+                HSEClockMode::Oscillator
+            },
+        })
+    }
 
-                (freq, 3)
+    /// Returns the system clock, PLL VCO and HSE frequencies, 0 when unused
+    const fn sources(&self) -> Result<(u32, u32, u32), Error> {
+        let sources = match self.mux {
+            ClockSrc::MSI(range) => (32_768 << (range as u8 + 1), 0, 0),
+            ClockSrc::HSI => (HSI_FREQ, 0, 0),
+            ClockSrc::HSE(freq) => (freq.0, 0, freq.0),
+            ClockSrc::PLL(src, mul, div) => {
+                let freq = src.freq();
+                if freq < PLL_IN_MIN || freq > PLL_IN_MAX {
+                    return Err(Error::PllInputOutOfRange);
+                }
+                let hse = match src {
+                    PLLSource::HSE(freq) => freq.0,
+                    PLLSource::HSI => 0,
+                };
+                let vco = freq * mul.factor();
+                (vco / div.divisor(), vco, hse)
             }
         };
 
-        self.rb.cfgr.modify(|_, w| unsafe {
-            w.sw()
-                .bits(sw_bits)
-                .hpre()
-                .bits(cfgr.ahb_pre as u8)
-                .ppre1()
-                .bits(cfgr.apb1_pre as u8)
-                .ppre2()
-                .bits(cfgr.apb2_pre as u8)
-        });
+        match VoltageRange::for_clocks(sources.0, sources.1, sources.2) {
+            Ok(_) => Ok(sources),
+            Err(e) => Err(e),
+        }
+    }
+}
 
-        let ahb_freq = match cfgr.ahb_pre {
-            AHBPrescaler::NotDivided => sys_clk,
-            pre => sys_clk / (1 << (pre as u8 - 7)),
-        };
+/// Returns the APB and APB timer frequencies for `pre`, timers run at twice the
+/// APB frequency when it is divided
+const fn apb_clocks(ahb_clk: u32, pre: APBPrescaler) -> (u32, u32) {
+    match pre {
+        APBPrescaler::NotDivided => (ahb_clk, ahb_clk),
+        pre => {
+            let freq = ahb_clk >> (pre as u8 - 3);
+            (freq, freq * 2)
+        }
+    }
+}
 
-        let (apb1_freq, apb1_tim_freq) = match cfgr.apb1_pre {
-            APBPrescaler::NotDivided => (ahb_freq, ahb_freq),
-            pre => {
-                let freq = ahb_freq / (1 << (pre as u8 - 3));
-                (freq, freq * 2)
-            }
-        };
+/// RCC peripheral#[derive(Clone)]
+pub struct Rcc {
+    pub clocks: Clocks,
+    pub(crate) rb: RCC,
+    config: Config,
+}
 
-        let (apb2_freq, apb2_tim_freq) = match cfgr.apb2_pre {
-            APBPrescaler::NotDivided => (ahb_freq, ahb_freq),
-            pre => {
-                let freq = ahb_freq / (1 << (pre as u8 - 3));
-                (freq, freq * 2)
-            }
-        };
+impl core::ops::Deref for Rcc {
+    type Target = RCC;
 
-        let clocks = Clocks {
-            sys_clk: sys_clk.hz(),
-            ahb_clk: ahb_freq.hz(),
-            apb1_clk: apb1_freq.hz(),
-            apb2_clk: apb2_freq.hz(),
-            apb1_tim_clk: apb1_tim_freq.hz(),
-            apb2_tim_clk: apb2_tim_freq.hz(),
-        };
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        &self.rb
+    }
+}
 
-        Rcc {
-            rb: self.rb,
-            clocks,
-            config: cfgr,
+impl Rcc {
+    /// Takes over the clocks as they are, for instance set up by a bootloader
+    ///
+    /// CFGR and ICSCR are decoded into the configuration and clocks without
+    /// reprogramming anything. `hse` is the HSE frequency, needed when the system
+    /// clock or the PLL runs from it.
+    pub fn from_current_config(rcc: RCC, hse: Option<Hertz>) -> Result<Rcc, Error> {
+        let config = Config::from_registers(&rcc, hse)?;
+        Ok(Rcc {
+            clocks: config.clocks()?,
+            rb: rcc,
+            config,
+        })
+    }
+
+    /// Applies `cfgr`, see [`Rcc::try_freeze`]
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration cannot be run at any voltage range.
+    #[inline]
+    pub fn freeze(self, cfgr: Config) -> Rcc {
+        match self.try_freeze(cfgr) {
+            Ok(rcc) => rcc,
+            Err((_, error)) => panic!("invalid clock configuration: {:?}", error),
+        }
+    }
+
+    /// Applies `cfgr`, selecting the voltage range and flash wait states for it
+    ///
+    /// On error the clocks are left untouched and `Rcc` is handed back, so another
+    /// configuration can be tried.
+    pub fn try_freeze(mut self, cfgr: Config) -> Result<Rcc, (Rcc, Error)> {
+        match self.set_config(cfgr) {
+            Ok(_) => Ok(self),
+            Err(error) => Err((self, error)),
         }
     }
 
+    /// Switches to `config` at runtime and returns the new clocks
+    ///
+    /// The previous configuration is kept if `config` cannot be run. Drivers keep
+    /// the divisors computed for the old clocks, pass the returned clocks to their
+    /// `set_clocks` methods. The timer monotonics and the embassy time driver
+    /// cannot follow a clock change and must not be running.
+    pub fn set_config(&mut self, config: Config) -> Result<Clocks, Error> {
+        let previous = core::mem::replace(&mut self.config, config);
+        if let Err(error) = self.try_update() {
+            self.config = previous;
+            return Err(error);
+        }
+        Ok(self.clocks)
+    }
+
+    /// Applies the configuration again, see [`Rcc::try_update`]
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration cannot be run at any voltage range.
     pub fn update(&mut self) {
-        let (sys_clk, sw_bits) = match self.config.mux {
+        self.try_update().unwrap()
+    }
+
+    /// Applies the configuration again
+    ///
+    /// The voltage range is raised and the flash wait states are added before the
+    /// clocks speed up, and they are lowered after the clocks slowed down.
+    pub fn try_update(&mut self) -> Result<(), Error> {
+        let range = self.config.voltage_range()?;
+        let mut clocks = self.config.clocks()?;
+        clocks.lsi_clk = self.clocks.lsi_clk;
+        let wait_state = clocks.ahb_clk.0 > range.zero_wait_state_max();
+
+        // speed up: raise the voltage and add the wait state first
+        let pwr = unsafe { &(*PWR::ptr()) };
+        PWR::enable(&self.rb);
+        let current_range = pwr.cr.read().vos().bits();
+        if (range as u8) < current_range {
+            set_voltage_range(pwr, range);
+        }
+        if wait_state {
+            set_wait_state(true);
+        }
+
+        let sw_bits = match self.config.mux {
             ClockSrc::MSI(range) => {
                 let range = range as u8;
                 // Set MSI range
@@ -349,70 +745,52 @@ impl Rcc {
                 self.rb.cr.modify(|_, w| w.msion().set_bit());
                 while self.rb.cr.read().msirdy().bit_is_clear() {}
 
-                let freq = 32_768 * (1 << (range + 1));
-                (freq, 0)
+                0
             }
             ClockSrc::HSI => {
                 // Enable HSI
                 self.rb.cr.modify(|_, w| w.hsion().set_bit());
                 while self.rb.cr.read().hsirdy().bit_is_clear() {}
 
-                (HSI_FREQ, 1)
+                1
             }
-            ClockSrc::HSE(freq) => {
-                // Enable HSE
-                self.rb.cr.modify(|_, w| w.hseon().set_bit());
-                while self.rb.cr.read().hserdy().bit_is_clear() {}
-
-                (freq.0, 2)
+            ClockSrc::HSE(_) => {
+                self.enable_hse();
+                2
             }
             ClockSrc::PLL(src, mul, div) => {
-                let (src_bit, freq) = match src {
-                    PLLSource::HSE(freq) => {
-                        // Enable HSE
-                        self.rb.cr.modify(|_, w| w.hseon().set_bit());
-                        while self.rb.cr.read().hserdy().bit_is_clear() {}
-                        (true, freq.0)
+                let src_bit = match src {
+                    PLLSource::HSE(_) => {
+                        self.enable_hse();
+                        true
                     }
                     PLLSource::HSI => {
                         // Enable HSI
                         self.rb.cr.modify(|_, w| w.hsion().set_bit());
                         while self.rb.cr.read().hsirdy().bit_is_clear() {}
-                        (false, 15_998_976)
+                        false
                     }
                 };
 
+                // The PLL cannot be disabled while it runs the system clock, run
+                // from its source meanwhile
+                if self.rb.cfgr.read().sws().bits() == 0b11 {
+                    let sw_bits = if src_bit { 2 } else { 1 };
+                    self.rb
+                        .cfgr
+                        .modify(|r, w| unsafe { w.bits(r.bits() & !0b11 | sw_bits) });
+                    while self.rb.cfgr.read().sws().bits() == 0b11 {}
+                }
+
                 // Disable PLL
                 self.rb.cr.modify(|_, w| w.pllon().clear_bit());
                 while self.rb.cr.read().pllrdy().bit_is_set() {}
 
-                let mul_bytes = mul as u8;
-                let div_bytes = div as u8;
-
-                let freq = match mul {
-                    PLLMul::Mul3 => freq * 3,
-                    PLLMul::Mul4 => freq * 4,
-                    PLLMul::Mul6 => freq * 6,
-                    PLLMul::Mul8 => freq * 8,
-                    PLLMul::Mul12 => freq * 12,
-                    PLLMul::Mul16 => freq * 16,
-                    PLLMul::Mul24 => freq * 24,
-                    PLLMul::Mul32 => freq * 32,
-                    PLLMul::Mul48 => freq * 48,
-                };
-
-                let freq = match div {
-                    PLLDiv::Div2 => freq / 2,
-                    PLLDiv::Div3 => freq / 3,
-                    PLLDiv::Div4 => freq / 4,
-                };
-                assert!(freq <= 24.mhz().0);
-
                 self.rb.cfgr.modify(|_, w| unsafe {
                     w.pllmul()
-                        .bits(mul_bytes)
+                        .bits(mul as u8)
                         .plldiv()
-                        .bits(div_bytes)
+                        .bits(div as u8)
                         .pllsrc()
                         .bit(src_bit)
                 });
@@ -421,7 +799,7 @@ impl Rcc {
                 self.rb.cr.modify(|_, w| w.pllon().set_bit());
                 while self.rb.cr.read().pllrdy().bit_is_clear() {}
 
-                (freq, 3)
+                3
             }
         };
 
@@ -435,41 +813,33 @@ impl Rcc {
                 .ppre2()
                 .bits(self.config.apb2_pre as u8)
         });
+        while self.rb.cfgr.read().sws().bits() != sw_bits {}
 
-        let ahb_freq = match self.config.ahb_pre {
-            AHBPrescaler::NotDivided => sys_clk,
-            pre => sys_clk / (1 << (pre as u8 - 7)),
-        };
-
-        let (apb1_freq, apb1_tim_freq) = match self.config.apb1_pre {
-            APBPrescaler::NotDivided => (ahb_freq, ahb_freq),
-            pre => {
-                let freq = ahb_freq / (1 << (pre as u8 - 3));
-                (freq, freq * 2)
-            }
-        };
-
-        let (apb2_freq, apb2_tim_freq) = match self.config.apb2_pre {
-            APBPrescaler::NotDivided => (ahb_freq, ahb_freq),
-            pre => {
-                let freq = ahb_freq / (1 << (pre as u8 - 3));
-                (freq, freq * 2)
-            }
-        };
+        // slow down: drop the wait state and lower the voltage last
+        if !wait_state {
+            set_wait_state(false);
+        }
+        if (range as u8) > current_range {
+            set_voltage_range(pwr, range);
+        }
 
-        let clocks = Clocks {
-            sys_clk: sys_clk.hz(),
-            ahb_clk: ahb_freq.hz(),
-            apb1_clk: apb1_freq.hz(),
-            apb2_clk: apb2_freq.hz(),
-            apb1_tim_clk: apb1_tim_freq.hz(),
-            apb2_tim_clk: apb2_tim_freq.hz(),
-        };
         self.clocks = clocks;
+        Ok(())
+    }
+
+    fn enable_hse(&self) {
+        // HSEBYP can only be written while the HSE is off
+        if self.rb.cr.read().hseon().bit_is_clear() {
+            let bypass = self.config.hse_mode == HSEClockMode::Bypass;
+            self.rb.cr.modify(|_, w| w.hsebyp().bit(bypass));
+            // Enable HSE
+            self.rb.cr.modify(|_, w| w.hseon().set_bit());
+        }
+        while self.rb.cr.read().hserdy().bit_is_clear() {}
     }
 
     pub fn enable_power(&mut self) {
-        self.rb.apb1enr.modify(|_, w| w.pwren().set_bit());
+        PWR::enable(&self.rb);
     }
 
     pub fn configure_mco<P>(&mut self, source: MCOSel, prescaler: MCODiv, output_pin: P)
@@ -513,6 +883,191 @@ impl Rcc {
         self.update();
     }
 
+    /// Measures the LSI frequency against the APB2 timer clock
+    ///
+    /// The LSI is routed to TIM10 channel 1, which is borrowed for the measurement.
+    /// The result is kept in [`Clocks::lsi_clk`] for the watchdog and the RTC.
+    /// Fails with [`Error::MeasurementTimeout`] if the LSI does not reach TIM10.
+    pub fn measure_lsi(&mut self, tim: &mut TIM10) -> Result<Hertz, Error> {
+        self.rb.csr.modify(|_, w| w.lsion().set_bit());
+        while self.rb.csr.read().lsirdy().bit_is_clear() {}
+
+        // TI1_RMP: LSI
+        let counts = self.capture_period(tim, 0b01)?;
+        let tim_clk = u64::from(self.clocks.apb2_tim_clk.0);
+        let lsi = (tim_clk * u64::from(CALIBRATION_CYCLES) / u64::from(counts)) as u32;
+        self.clocks.lsi_clk = lsi.hz();
+        LSI_CLK.store(lsi, Ordering::Relaxed);
+        Ok(self.clocks.lsi_clk)
+    }
+
+    /// Trims the MSI against the LSE and returns the resulting system clock
+    ///
+    /// The system clock has to run from the MSI and the LSE has to be running, for
+    /// instance after `Rtc::new`. TIM10 is borrowed for the measurement.
+    pub fn trim_msi(&mut self, tim: &mut TIM10) -> Result<Hertz, Error> {
+        if !matches!(self.config.mux, ClockSrc::MSI(_)) {
+            return Err(Error::WrongClockSource);
+        }
+        // MSITRIM is added to the factory calibration, as a signed value
+        self.trim(tim, i8::MIN.into(), i8::MAX.into(), |rb, trim| {
+            rb.icscr
+                .modify(|_, w| unsafe { w.msitrim().bits(trim as i8 as u8) })
+        })
+    }
+
+    /// Trims the HSI against the LSE and returns the resulting system clock
+    ///
+    /// The system clock has to run from the HSI, directly or through the PLL, and the
+    /// LSE has to be running. TIM10 is borrowed for the measurement.
+    pub fn trim_hsi(&mut self, tim: &mut TIM10) -> Result<Hertz, Error> {
+        match self.config.mux {
+            ClockSrc::HSI | ClockSrc::PLL(PLLSource::HSI, _, _) => {}
+            _ => return Err(Error::WrongClockSource),
+        }
+        self.trim(tim, 0, 31, |rb, trim| {
+            rb.icscr
+                .modify(|_, w| unsafe { w.hsitrim().bits(trim as u8) })
+        })
+    }
+
+    /// Searches the trim value that brings the timer clock closest to its nominal
+    /// frequency, counted over LSE cycles, and updates the clocks to the result
+    fn trim<F>(&mut self, tim: &mut TIM10, min: i32, max: i32, set_trim: F) -> Result<Hertz, Error>
+    where
+        F: Fn(&RCC, i32),
+    {
+        if self.rb.csr.read().lserdy().bit_is_clear() {
+            return Err(Error::LseNotReady);
+        }
+        let nominal = self.clocks.apb2_tim_clk.0;
+        let target =
+            (u64::from(nominal) * u64::from(CALIBRATION_CYCLES) / u64::from(LSE_FREQ)) as u32;
+        // TI1_RMP: LSE
+        let mut measure = |rcc: &mut Rcc, trim: i32| {
+            set_trim(&rcc.rb, trim);
+            rcc.capture_period(tim, 0b10)
+        };
+
+        // the oscillator speeds up with the trim value
+        let (mut low, mut high) = (min, max);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if measure(self, mid)? < target {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        let mut best = (low, measure(self, low)?);
+        if low > min {
+            let below = measure(self, low - 1)?;
+            if below.abs_diff(target) < best.1.abs_diff(target) {
+                best = (low - 1, below);
+            }
+        }
+        set_trim(&self.rb, best.0);
+
+        let sys_clk = u64::from(self.clocks.sys_clk.0) * u64::from(best.1) / u64::from(target);
+        self.clocks = self.clocks.scaled(sys_clk as u32);
+        Ok(self.clocks.sys_clk)
+    }
+
+    /// Returns the timer clock ticks of `CALIBRATION_CYCLES` cycles of the TIM10
+    /// input selected by `remap`
+    ///
+    /// Fails if a capture takes longer than a counter period, which happens when
+    /// the input clock does not run.
+    fn capture_period(&mut self, tim: &mut TIM10, remap: u8) -> Result<u32, Error> {
+        TIM10::enable(&self.rb);
+        TIM10::reset(&self.rb);
+
+        tim.or.write(|w| unsafe { w.ti1_rmp().bits(remap) });
+        tim.arr.write(|w| unsafe { w.bits(0xffff) });
+        // CC1 captures TI1 every 8 input cycles
+        tim.ccmr1_input()
+            .write(|w| unsafe { w.bits(0b11 << 2 | 0b01) });
+        tim.ccer.write(|w| w.cc1e().set_bit());
+        tim.cr1.modify(|_, w| w.cen().set_bit());
+
+        let counts = count_captures(tim);
+
+        tim.cr1.modify(|_, w| w.cen().clear_bit());
+        TIM10::disable(&self.rb);
+        counts
+    }
+
+    /// Returns the configuration the clocks were set up with
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Enables the clock security system on the HSE
+    ///
+    /// The detector starts once the HSE is ready. On a failure the NMI is raised,
+    /// see [`css_nmi_handler`].
+    pub fn enable_css(&mut self) {
+        self.rb.cr.modify(|_, w| w.csson().set_bit());
+    }
+
+    /// Disables the clock security system on the HSE
+    pub fn disable_css(&mut self) {
+        self.rb.cr.modify(|_, w| w.csson().clear_bit());
+    }
+
+    /// Takes over the clocks after an HSE failure, once per failure
+    ///
+    /// Returns the new clocks, which the drivers that depend on them, like the
+    /// serial baud rates, have to be reconfigured with.
+    pub fn css_recovered(&mut self) -> Option<Clocks> {
+        if !CSS_EVENT.swap(false, Ordering::Acquire) {
+            return None;
+        }
+        let config = Config::from_registers(&self.rb, None).ok()?;
+        let mut clocks = config.clocks().ok()?;
+        clocks.lsi_clk = self.clocks.lsi_clk;
+        self.config = config;
+        self.clocks = clocks;
+        Some(clocks)
+    }
+
+    /// Enables the clock security system on the LSE
+    ///
+    /// The LSE and the LSI have to run, and the RTC clock be selected, before.
+    /// A failure raises the RCC interrupt, see [`lse_css_handler`].
+    #[cfg(feature = "_cat3plus")]
+    pub fn enable_lse_css(&mut self) {
+        // LSECSSIE, then LSECSSON, which the PACs do not all describe
+        bb::set(&self.rb.cir, 14);
+        bb::set(&self.rb.csr, 11);
+    }
+
+    /// Returns why the device reset, then clears the reset and standby flags
+    ///
+    /// The flags accumulate over resets until they are cleared, call it once
+    /// early after boot.
+    pub fn reset_reason(&mut self, pwr: &mut Pwr) -> ResetReason {
+        // raw bits, OBLRSTF is not described by all PACs
+        let csr = self.rb.csr.read().bits();
+        let flag = |bit: u32| csr & (1 << bit) != 0;
+        let reason = ResetReason {
+            low_power: flag(31),
+            window_watchdog: flag(30),
+            independent_watchdog: flag(29),
+            software: flag(28),
+            power_on: flag(27),
+            pin: flag(26),
+            option_byte: flag(25),
+            standby: pwr.is_standby_flag_set(),
+            wakeup: pwr.is_wakeup_flag_set(),
+        };
+
+        self.rb.csr.modify(|_, w| w.rmvf().set_bit());
+        pwr.clear_standby_flag();
+        pwr.clear_wakeup_flag();
+        reason
+    }
+
     /// Get the current system clock source.
     ///
     /// Returns the active clock source as indicated by the SWS (System clock switch status) bits.
@@ -530,24 +1085,155 @@ impl Rcc {
     }
 }
 
+/// System clock to run from after an HSE failure
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CssFallback {
+    /// Stay on the MSI, which the hardware switched to
+    MSI,
+    /// Switch to the 16 MHz HSI
+    HSI,
+}
+
+/// Handles an HSE failure, call it from the `NonMaskableInt` handler
+///
+/// The hardware has already stopped the HSE and the PLL and switched the system
+/// clock to the MSI. This clears the CSS interrupt, switches to the HSI if asked
+/// and returns the clocks now running, or `None` if the CSS did not fire. It
+/// only touches registers and an atomic flag, so it is safe to call from the
+/// NMI. Call [`Rcc::css_recovered`] from thread mode afterwards, for instance
+/// from an interrupt the NMI handler pends, to update `Rcc` and the drivers.
+pub fn css_nmi_handler(fallback: CssFallback) -> Option<Clocks> {
+    // NOTE(unsafe) only the CSS flag and the clock switch are written
+    let rcc = unsafe { &*RCC::ptr() };
+    if rcc.cir.read().cssf().bit_is_clear() {
+        return None;
+    }
+    // CSSC
+    bb::set(&rcc.cir, 23);
+
+    if fallback == CssFallback::HSI {
+        // HSION
+        bb::set(&rcc.cr, 0);
+        while rcc.cr.read().hsirdy().bit_is_clear() {}
+        // an HSE up to 8 MHz runs in range 2 without a wait state, 16 MHz needs
+        // one, and range 3 does not allow 16 MHz at all
+        let pwr = unsafe { &(*PWR::ptr()) };
+        // PWREN
+        bb::set(&rcc.apb1enr, 28);
+        if pwr.cr.read().vos().bits() == VoltageRange::Range3 as u8 {
+            set_voltage_range(pwr, VoltageRange::Range2);
+        }
+        set_wait_state(true);
+        rcc.cfgr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !0b11 | 0b01) });
+        while rcc.cfgr.read().sws().bits() != 0b01 {}
+    }
+
+    CSS_EVENT.store(true, Ordering::Release);
+    let mut clocks = Config::from_registers(rcc, None).ok()?.clocks().ok()?;
+    clocks.lsi_clk = Hertz(LSI_CLK.load(Ordering::Relaxed));
+    Some(clocks)
+}
+
+/// Handles an LSE failure, returns `true` if the LSE CSS fired
+///
+/// Clears the LSE CSS interrupt. The RTC stops with the LSE, it has to be moved
+/// to the LSI through a backup domain reset.
+#[cfg(feature = "_cat3plus")]
+pub fn lse_css_handler() -> bool {
+    // NOTE(unsafe) only the LSE CSS flag is written
+    let rcc = unsafe { &*RCC::ptr() };
+    // LSECSSD
+    if rcc.csr.read().bits() & (1 << 12) == 0 {
+        return false;
+    }
+    // LSECSSC
+    bb::set(&rcc.cir, 22);
+    true
+}
+
+/// Keeps the clock of `P` running in sleep mode, or stops it
+///
+/// All peripheral clocks run in sleep mode after reset. Stopping the clock of an
+/// unused peripheral saves power, but a peripheral whose clock is stopped makes
+/// no progress and raises no event to wake the core. The drivers of peripherals
+/// that keep working on their own, like PWM outputs, enable the clock when they
+/// are created, their `set_sleep_clock` methods override it.
+pub fn set_sleep_clock<P: LPEnable>(enabled: bool) {
+    // NOTE(unsafe) the bit is written atomically through bit-banding
+    let rcc = unsafe { &*RCC::ptr() };
+    if enabled {
+        P::enable_in_low_power(rcc);
+    } else {
+        P::disable_in_low_power(rcc);
+    }
+}
+
+/// Sums the timer ticks between `CALIBRATION_CYCLES / 8` captures of TIM10
+fn count_captures(tim: &TIM10) -> Result<u32, Error> {
+    let capture = || {
+        let mut last = tim.cnt.read().bits() as u16;
+        let mut waited = 0;
+        while tim.sr.read().cc1if().bit_is_clear() {
+            let now = tim.cnt.read().bits() as u16;
+            waited += u32::from(now.wrapping_sub(last));
+            last = now;
+            // the ticks between two captures have to fit the 16-bit counter
+            if waited > 0xffff {
+                return Err(Error::MeasurementTimeout);
+            }
+        }
+        // reading the capture clears the flag
+        Ok(tim.ccr1().read().bits() as u16)
+    };
+
+    let mut previous = capture()?;
+    let mut counts = 0;
+    for _ in 0..CALIBRATION_CYCLES / 8 {
+        let next = capture()?;
+        counts += u32::from(next.wrapping_sub(previous));
+        previous = next;
+    }
+    Ok(counts.max(1))
+}
+
 /// Extension trait that freezes the `RCC` peripheral with provided clocks configuration
 pub trait RccExt {
     fn freeze(self, config: Config) -> Rcc;
+    fn try_freeze(self, config: Config) -> Result<Rcc, (Rcc, Error)>;
+    /// Takes over the clocks as they are, see [`Rcc::from_current_config`]
+    ///
+    /// The HSE frequency is unknown here: when the system clock or the PLL runs
+    /// from the HSE, a register holds a reserved value or the decoded configuration
+    /// has no valid clocks, the MSI reset configuration and its clocks are reported
+    /// instead of the running ones, and the clocks the drivers use are wrong. Use [`Rcc::from_current_config`] with the HSE
+    /// frequency then, or apply a known configuration with [`RccExt::freeze`].
     fn constrain(self) -> Rcc;
 }
 
 impl RccExt for RCC {
     fn constrain(self) -> Rcc {
+        let (config, clocks) = Config::from_registers(&self, None)
+            .and_then(|config| Ok((config, config.clocks()?)))
+            .unwrap_or_else(|_| {
+                let config = Config::default();
+                // the MSI reset configuration always has valid clocks
+                (config, config.clocks().unwrap_or_default())
+            });
         Rcc {
             rb: self,
-            clocks: Clocks::default(),
-            config: Config::default(),
+            clocks,
+            config,
         }
     }
 
     fn freeze(self, rcc_cfg: Config) -> Rcc {
         self.constrain().freeze(rcc_cfg)
     }
+
+    fn try_freeze(self, rcc_cfg: Config) -> Result<Rcc, (Rcc, Error)> {
+        self.constrain().try_freeze(rcc_cfg)
+    }
 }
 
 /// Frozen clock frequencies
@@ -561,6 +1247,7 @@ pub struct Clocks {
     apb1_tim_clk: Hertz,
     apb2_clk: Hertz,
     apb2_tim_clk: Hertz,
+    lsi_clk: Hertz,
 }
 
 impl Default for Clocks {
@@ -573,6 +1260,7 @@ impl Default for Clocks {
             apb1_tim_clk: freq,
             apb2_clk: freq,
             apb2_tim_clk: freq,
+            lsi_clk: LSI_FREQ.hz(),
         }
     }
 }
@@ -607,4 +1295,162 @@ impl Clocks {
     pub fn apb2_tim_clk(&self) -> Hertz {
         self.apb2_tim_clk
     }
+
+    /// Returns the LSI frequency, as measured by [`Rcc::measure_lsi`] or the typical one
+    pub fn lsi_clk(&self) -> Hertz {
+        self.lsi_clk
+    }
+
+    /// Scales the bus clocks to the measured system clock
+    fn scaled(self, sys_clk: u32) -> Clocks {
+        let scale =
+            |clk: Hertz| (u64::from(clk.0) * u64::from(sys_clk) / u64::from(self.sys_clk.0)) as u32;
+        Clocks {
+            sys_clk: sys_clk.hz(),
+            ahb_clk: scale(self.ahb_clk).hz(),
+            apb1_clk: scale(self.apb1_clk).hz(),
+            apb1_tim_clk: scale(self.apb1_tim_clk).hz(),
+            apb2_clk: scale(self.apb2_clk).hz(),
+            apb2_tim_clk: scale(self.apb2_tim_clk).hz(),
+            lsi_clk: self.lsi_clk,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLL_MULS: [(PLLMul, u32); 9] = [
+        (PLLMul::Mul3, 3),
+        (PLLMul::Mul4, 4),
+        (PLLMul::Mul6, 6),
+        (PLLMul::Mul8, 8),
+        (PLLMul::Mul12, 12),
+        (PLLMul::Mul16, 16),
+        (PLLMul::Mul24, 24),
+        (PLLMul::Mul32, 32),
+        (PLLMul::Mul48, 48),
+    ];
+    const PLL_DIVS: [(PLLDiv, u32); 3] = [(PLLDiv::Div2, 2), (PLLDiv::Div3, 3), (PLLDiv::Div4, 4)];
+
+    fn sys_clk(config: Config) -> Result<u32, Error> {
+        config.clocks().map(|clocks| clocks.sys_clk().0)
+    }
+
+    #[test]
+    fn msi_ranges() {
+        let ranges = [
+            (MSIRange::Range0, 65_536),
+            (MSIRange::Range1, 131_072),
+            (MSIRange::Range2, 262_144),
+            (MSIRange::Range3, 524_288),
+            (MSIRange::Range4, 1_048_576),
+            (MSIRange::Range5, 2_097_152),
+            (MSIRange::Range6, 4_194_304),
+        ];
+        for (range, freq) in ranges {
+            assert_eq!(sys_clk(Config::msi(range)), Ok(freq));
+        }
+        assert_eq!(sys_clk(Config::default()), Ok(2_097_152));
+    }
+
+    #[test]
+    fn hsi_and_hse() {
+        assert_eq!(sys_clk(Config::hsi()), Ok(16_000_000));
+        assert_eq!(sys_clk(Config::hse(8.mhz())), Ok(8_000_000));
+        assert_eq!(sys_clk(Config::hse(32.mhz())), Ok(32_000_000));
+        assert_eq!(sys_clk(Config::hse(33.mhz())), Err(Error::SysClkTooHigh));
+    }
+
+    #[test]
+    fn pll_combinations() {
+        for source in [
+            PLLSource::HSI,
+            PLLSource::HSE(8.mhz()),
+            PLLSource::HSE(2.mhz()),
+        ] {
+            let input = match source {
+                PLLSource::HSI => 16_000_000,
+                PLLSource::HSE(freq) => freq.0,
+            };
+            for (mul, factor) in PLL_MULS {
+                for (div, divisor) in PLL_DIVS {
+                    let vco = input * factor;
+                    let expected = if vco / divisor > 32_000_000 {
+                        Err(Error::SysClkTooHigh)
+                    } else if vco > 96_000_000 {
+                        Err(Error::VcoTooHigh)
+                    } else {
+                        Ok(vco / divisor)
+                    };
+                    assert_eq!(sys_clk(Config::pll(source, mul, div)), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pll_input_range() {
+        let config = Config::pll(PLLSource::HSE(1.mhz()), PLLMul::Mul4, PLLDiv::Div2);
+        assert_eq!(sys_clk(config), Err(Error::PllInputOutOfRange));
+        let config = Config::pll(PLLSource::HSE(25.mhz()), PLLMul::Mul3, PLLDiv::Div3);
+        assert_eq!(sys_clk(config), Err(Error::PllInputOutOfRange));
+    }
+
+    #[test]
+    fn ahb_prescalers() {
+        let prescalers = [
+            (AHBPrescaler::NotDivided, 1),
+            (AHBPrescaler::Div2, 2),
+            (AHBPrescaler::Div4, 4),
+            (AHBPrescaler::Div8, 8),
+            (AHBPrescaler::Div16, 16),
+            (AHBPrescaler::Div64, 64),
+            (AHBPrescaler::Div128, 128),
+            (AHBPrescaler::Div256, 256),
+            (AHBPrescaler::Div512, 512),
+        ];
+        for (pre, divisor) in prescalers {
+            let clocks = Config::hsi().ahb_pre(pre).clocks().unwrap();
+            assert_eq!(clocks.sys_clk().0, 16_000_000);
+            assert_eq!(clocks.ahb_clk().0, 16_000_000 / divisor);
+            assert_eq!(clocks.apb1_clk().0, 16_000_000 / divisor);
+            assert_eq!(clocks.apb2_clk().0, 16_000_000 / divisor);
+        }
+    }
+
+    #[test]
+    fn apb_prescalers() {
+        let prescalers = [
+            (APBPrescaler::NotDivided, 1),
+            (APBPrescaler::Div2, 2),
+            (APBPrescaler::Div4, 4),
+            (APBPrescaler::Div8, 8),
+            (APBPrescaler::Div16, 16),
+        ];
+        for (pre, divisor) in prescalers {
+            // timers run at twice the APB clock when it is divided
+            let timer = if divisor == 1 { 1 } else { 2 };
+
+            let clocks = Config::hsi()
+                .ahb_pre(AHBPrescaler::Div2)
+                .apb1_pre(pre)
+                .clocks()
+                .unwrap();
+            assert_eq!(clocks.apb1_clk().0, 8_000_000 / divisor);
+            assert_eq!(clocks.apb1_tim_clk().0, 8_000_000 / divisor * timer);
+            assert_eq!(clocks.apb2_clk().0, 8_000_000);
+            assert_eq!(clocks.apb2_tim_clk().0, 8_000_000);
+
+            let clocks = Config::hsi()
+                .ahb_pre(AHBPrescaler::Div2)
+                .apb2_pre(pre)
+                .clocks()
+                .unwrap();
+            assert_eq!(clocks.apb2_clk().0, 8_000_000 / divisor);
+            assert_eq!(clocks.apb2_tim_clk().0, 8_000_000 / divisor * timer);
+            assert_eq!(clocks.apb1_clk().0, 8_000_000);
+        }
+    }
 }
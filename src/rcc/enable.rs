@@ -0,0 +1,280 @@
+//! Peripheral clock enable, reset and bus clock queries
+//!
+//! The traits are implemented for the PAC peripherals, so drivers, including
+//! third-party ones, can manage the clocks of the peripheral type they own.
+//!
+//! ```ignore
+//! use stm32l1xx_hal::rcc::{BusClock, Enable, Reset};
+//!
+//! SPI2::enable(&rcc);
+//! SPI2::reset(&rcc);
+//! let pclk = SPI2::clock(&rcc.clocks);
+//! ```
+use super::Clocks;
+use crate::bb;
+use crate::stm32::rcc::{self, RegisterBlock as RccRB};
+use crate::stm32::*;
+use crate::time::Hertz;
+
+/// AHB bus
+pub struct AHB;
+/// APB1 bus
+pub struct APB1;
+/// APB2 bus
+pub struct APB2;
+
+impl AHB {
+    fn enr(rcc: &RccRB) -> &rcc::AHBENR {
+        &rcc.ahbenr
+    }
+
+    fn rstr(rcc: &RccRB) -> &rcc::AHBRSTR {
+        &rcc.ahbrstr
+    }
+
+    fn lpenr(rcc: &RccRB) -> &rcc::AHBLPENR {
+        &rcc.ahblpenr
+    }
+}
+
+impl APB1 {
+    fn enr(rcc: &RccRB) -> &rcc::APB1ENR {
+        &rcc.apb1enr
+    }
+
+    fn rstr(rcc: &RccRB) -> &rcc::APB1RSTR {
+        &rcc.apb1rstr
+    }
+
+    fn lpenr(rcc: &RccRB) -> &rcc::APB1LPENR {
+        &rcc.apb1lpenr
+    }
+}
+
+impl APB2 {
+    fn enr(rcc: &RccRB) -> &rcc::APB2ENR {
+        &rcc.apb2enr
+    }
+
+    fn rstr(rcc: &RccRB) -> &rcc::APB2RSTR {
+        &rcc.apb2rstr
+    }
+
+    fn lpenr(rcc: &RccRB) -> &rcc::APB2LPENR {
+        &rcc.apb2lpenr
+    }
+}
+
+/// Bus a peripheral is connected to
+pub trait RccBus {
+    type Bus;
+}
+
+/// Clock frequency of a bus, or of the bus a peripheral is connected to
+pub trait BusClock {
+    fn clock(clocks: &Clocks) -> Hertz;
+}
+
+/// Timer clock frequency of an APB bus, or of the bus a timer is connected to
+pub trait BusTimerClock {
+    fn timer_clock(clocks: &Clocks) -> Hertz;
+}
+
+impl BusClock for AHB {
+    fn clock(clocks: &Clocks) -> Hertz {
+        clocks.ahb_clk()
+    }
+}
+
+impl BusClock for APB1 {
+    fn clock(clocks: &Clocks) -> Hertz {
+        clocks.apb1_clk()
+    }
+}
+
+impl BusClock for APB2 {
+    fn clock(clocks: &Clocks) -> Hertz {
+        clocks.apb2_clk()
+    }
+}
+
+impl BusTimerClock for APB1 {
+    fn timer_clock(clocks: &Clocks) -> Hertz {
+        clocks.apb1_tim_clk()
+    }
+}
+
+impl BusTimerClock for APB2 {
+    fn timer_clock(clocks: &Clocks) -> Hertz {
+        clocks.apb2_tim_clk()
+    }
+}
+
+impl<T> BusClock for T
+where
+    T: RccBus,
+    T::Bus: BusClock,
+{
+    fn clock(clocks: &Clocks) -> Hertz {
+        T::Bus::clock(clocks)
+    }
+}
+
+impl<T> BusTimerClock for T
+where
+    T: RccBus,
+    T::Bus: BusTimerClock,
+{
+    fn timer_clock(clocks: &Clocks) -> Hertz {
+        T::Bus::timer_clock(clocks)
+    }
+}
+
+/// Peripheral clock gating
+pub trait Enable: RccBus {
+    /// Enables the peripheral clock
+    fn enable(rcc: &RccRB);
+
+    /// Disables the peripheral clock
+    fn disable(rcc: &RccRB);
+
+    /// Returns `true` if the peripheral clock is enabled
+    fn is_enabled() -> bool;
+}
+
+/// Peripheral clock gating in sleep mode
+pub trait LPEnable: RccBus {
+    /// Keeps the peripheral clock running in sleep mode
+    fn enable_in_low_power(rcc: &RccRB);
+
+    /// Stops the peripheral clock in sleep mode
+    fn disable_in_low_power(rcc: &RccRB);
+
+    /// Returns `true` if the peripheral clock runs in sleep mode
+    fn is_enabled_in_low_power() -> bool;
+}
+
+/// Peripheral reset
+pub trait Reset: RccBus {
+    /// Resets the peripheral registers
+    fn reset(rcc: &RccRB);
+}
+
+// The bits are written raw: they sit at the same position in the enable, reset
+// and sleep mode enable registers, which some PACs describe only partially. The
+// sleep mode enables are bit-banded, so drivers can change them at any time.
+macro_rules! bus {
+    ($($PER:ident => ($busX:ident, $bit:literal),)+) => {
+        $(
+            impl RccBus for $PER {
+                type Bus = $busX;
+            }
+
+            impl Enable for $PER {
+                #[inline(always)]
+                fn enable(rcc: &RccRB) {
+                    $busX::enr(rcc).modify(|r, w| unsafe { w.bits(r.bits() | (1 << $bit)) });
+                }
+
+                #[inline(always)]
+                fn disable(rcc: &RccRB) {
+                    $busX::enr(rcc).modify(|r, w| unsafe { w.bits(r.bits() & !(1 << $bit)) });
+                }
+
+                #[inline(always)]
+                fn is_enabled() -> bool {
+                    let rcc = unsafe { &*RCC::ptr() };
+                    $busX::enr(rcc).read().bits() & (1 << $bit) != 0
+                }
+            }
+
+            impl LPEnable for $PER {
+                #[inline(always)]
+                fn enable_in_low_power(rcc: &RccRB) {
+                    bb::set($busX::lpenr(rcc), $bit);
+                }
+
+                #[inline(always)]
+                fn disable_in_low_power(rcc: &RccRB) {
+                    bb::clear($busX::lpenr(rcc), $bit);
+                }
+
+                #[inline(always)]
+                fn is_enabled_in_low_power() -> bool {
+                    let rcc = unsafe { &*RCC::ptr() };
+                    $busX::lpenr(rcc).read().bits() & (1 << $bit) != 0
+                }
+            }
+
+            impl Reset for $PER {
+                #[inline(always)]
+                fn reset(rcc: &RccRB) {
+                    $busX::rstr(rcc).modify(|r, w| unsafe { w.bits(r.bits() | (1 << $bit)) });
+                    $busX::rstr(rcc).modify(|r, w| unsafe { w.bits(r.bits() & !(1 << $bit)) });
+                }
+            }
+        )+
+    }
+}
+
+bus! {
+    GPIOA => (AHB, 0),
+    GPIOB => (AHB, 1),
+    GPIOC => (AHB, 2),
+    GPIOD => (AHB, 3),
+    GPIOH => (AHB, 5),
+    CRC => (AHB, 12),
+    FLASH => (AHB, 15),
+    DMA1 => (AHB, 24),
+
+    TIM2 => (APB1, 0),
+    TIM3 => (APB1, 1),
+    TIM4 => (APB1, 2),
+    TIM6 => (APB1, 4),
+    TIM7 => (APB1, 5),
+    LCD => (APB1, 9),
+    WWDG => (APB1, 11),
+    SPI2 => (APB1, 14),
+    SPI3 => (APB1, 15),
+    USART2 => (APB1, 17),
+    USART3 => (APB1, 18),
+    I2C1 => (APB1, 21),
+    I2C2 => (APB1, 22),
+    USB => (APB1, 23),
+    PWR => (APB1, 28),
+    DAC => (APB1, 29),
+    COMP => (APB1, 31),
+
+    SYSCFG => (APB2, 0),
+    TIM9 => (APB2, 2),
+    TIM10 => (APB2, 3),
+    TIM11 => (APB2, 4),
+    ADC => (APB2, 9),
+    SPI1 => (APB2, 12),
+    USART1 => (APB2, 14),
+}
+
+// Category 3 and higher parts only
+#[cfg(feature = "_cat3plus")]
+bus! {
+    DMA2 => (AHB, 25),
+    AES => (AHB, 27),
+    FSMC => (AHB, 30),
+    TIM5 => (APB1, 3),
+    SDIO => (APB2, 11),
+}
+
+#[cfg(feature = "stm32l100")]
+bus! {
+    USART4 => (APB1, 19),
+    USART5 => (APB1, 20),
+}
+
+#[cfg(any(feature = "stm32l151", feature = "stm32l152", feature = "stm32l162"))]
+bus! {
+    GPIOE => (AHB, 4),
+    GPIOF => (AHB, 6),
+    GPIOG => (AHB, 7),
+    UART4 => (APB1, 19),
+    UART5 => (APB1, 20),
+}
@@ -1,6 +1,8 @@
 use crate::bb;
+use crate::rcc::{Clocks, Enable, HSEClockMode};
 use crate::stm32::rtc::{dr, tr};
 use crate::stm32::{rcc::RegisterBlock, EXTI, PWR, RCC, RTC};
+use crate::time::Hertz;
 use cast::u16;
 use core::convert::TryInto;
 use core::fmt;
@@ -25,6 +27,63 @@ pub enum Event {
 pub struct Lse;
 /// RTC clock source LSI oscillator clock (type state)
 pub struct Lsi;
+/// RTC clock source HSE clock divided by RTCPRE (type state)
+///
+/// Its frequency depends on the board, so it has no [`ClockSource`] impl.
+pub struct Hse;
+
+/// HSE divider for the RTC and LCD clock (RTCPRE)
+///
+/// The divided clock must not exceed 1 MHz.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HSEPrescaler {
+    Div2 = 0b00,
+    Div4 = 0b01,
+    Div8 = 0b10,
+    Div16 = 0b11,
+}
+
+impl HSEPrescaler {
+    const ALL: [HSEPrescaler; 4] = [
+        HSEPrescaler::Div2,
+        HSEPrescaler::Div4,
+        HSEPrescaler::Div8,
+        HSEPrescaler::Div16,
+    ];
+
+    const fn divisor(self) -> u32 {
+        2 << self as u32
+    }
+}
+
+/// Highest RTC clock the HSE may be divided down to
+const HSE_RTCCLK_MAX: u32 = 1_000_000;
+
+/// RTC clock source with its nominal frequency
+pub trait ClockSource {
+    /// RTCCLK frequency in Hz
+    const FREQUENCY: u32;
+
+    /// Returns the RTCCLK frequency in Hz, measured if `clocks` knows it
+    fn frequency(_clocks: &Clocks) -> u32 {
+        Self::FREQUENCY
+    }
+}
+
+impl ClockSource for Lse {
+    const FREQUENCY: u32 = 32_768;
+}
+
+impl ClockSource for Lsi {
+    const FREQUENCY: u32 = 37_000;
+
+    /// Returns the LSI frequency found by [`Rcc::measure_lsi`](crate::rcc::Rcc::measure_lsi),
+    /// the nominal one until it ran
+    fn frequency(clocks: &Clocks) -> u32 {
+        clocks.lsi_clk().0
+    }
+}
 
 /// Real Time Clock peripheral
 pub struct Rtc<CS = Lse> {
@@ -187,6 +246,15 @@ impl Rtc<Lsi> {
         result
     }
 
+    /// Create and enable a new RTC with internal crystal, with the prescalers set for
+    /// a 1 Hz calendar clock from the measured LSI frequency.
+    ///
+    /// See [`Rcc::measure_lsi`](crate::rcc::Rcc::measure_lsi).
+    pub fn lsi_with_frequency(regs: RTC, pwr: &mut PWR, lsi: Hertz) -> Self {
+        let prediv_s = (lsi.0 + 64) / 128;
+        Self::lsi_with_config(regs, pwr, u16(prediv_s.clamp(1, 1 << 15) - 1).unwrap(), 127)
+    }
+
     fn enable_lsi(&mut self, rcc: &RegisterBlock) {
         // Force a reset of the backup domain.
         self.backup_reset(rcc);
@@ -196,10 +264,106 @@ impl Rtc<Lsi> {
     }
 }
 
+impl Rtc<Hse> {
+    /// Create and enable a new RTC clocked from the HSE, with the RTCPRE divider
+    /// and the prescalers set for a 1 Hz calendar clock from `hse`.
+    ///
+    /// The HSE is started in `mode` if it does not run yet. Fails if no divider
+    /// setting gives exactly 1 Hz.
+    pub fn new_hse(
+        regs: RTC,
+        pwr: &mut PWR,
+        hse: Hertz,
+        mode: HSEClockMode,
+    ) -> Result<Self, Error> {
+        for prescaler in HSEPrescaler::ALL {
+            let div = prescaler.divisor();
+            let rtcclk = hse.0 / div;
+            if !hse.0.is_multiple_of(div) || rtcclk > HSE_RTCCLK_MAX {
+                continue;
+            }
+            // The largest asynchronous prescaler draws the least power
+            if let Some(prediv_a) = (1..=128)
+                .rev()
+                .find(|a| rtcclk.is_multiple_of(*a) && rtcclk / a <= 1 << 15)
+            {
+                let prediv_s = (rtcclk / prediv_a - 1) as u16;
+                let prediv_a = (prediv_a - 1) as u8;
+                return Ok(Self::hse_with_config(
+                    regs, pwr, mode, prescaler, prediv_s, prediv_a,
+                ));
+            }
+        }
+        Err(Error::InvalidInputData)
+    }
+
+    /// Create and enable a new RTC clocked from the HSE divided by `prescaler`,
+    /// and configure its prescalers.
+    ///
+    /// The LCD shares the divided clock. The HSE is started in `mode` if it does
+    /// not run yet, a running HSE is kept as it is.
+    pub fn hse_with_config(
+        regs: RTC,
+        pwr: &mut PWR,
+        mode: HSEClockMode,
+        prescaler: HSEPrescaler,
+        prediv_s: u16,
+        prediv_a: u8,
+    ) -> Self {
+        let mut result = Self {
+            regs,
+            _clock_source: PhantomData,
+        };
+
+        unsafe {
+            let rcc = &(*RCC::ptr());
+            // As per the sample code, unlock comes first. (Enable PWR and DBP)
+            result.unlock(rcc, pwr);
+            // If necessary, enable the HSE.
+            if rcc.cr.read().hseon().bit_is_clear() {
+                result.enable_hse(rcc, mode);
+            }
+            // RTCSEL can only be changed by a backup domain reset, and RTCPRE only
+            // while the HSE does not clock the RTC.
+            let rtcsel = rcc.csr.read().rtcsel().bits();
+            let rtcpre = (rcc.cr.read().bits() >> 29) & 0b11;
+            if rtcsel == 1 || rtcsel == 2 || (rtcsel == 3 && rtcpre != prescaler as u32) {
+                result.backup_reset(rcc);
+            }
+            // Written raw, the PACs name the RTCPRE field differently
+            rcc.cr
+                .modify(|r, w| w.bits(r.bits() & !(0b11 << 29) | (prescaler as u32) << 29));
+            // Set clock source to HSE.
+            rcc.csr.modify(|_, w| w.rtcsel().bits(3));
+            result.enable(rcc);
+        }
+
+        result.modify(|regs| {
+            // Set 24 Hour
+            regs.cr.modify(|_, w| w.fmt().clear_bit());
+            // Set prescalers
+            regs.prer.modify(|_, w| unsafe {
+                w.prediv_s().bits(prediv_s);
+                w.prediv_a().bits(prediv_a)
+            })
+        });
+
+        result
+    }
+
+    fn enable_hse(&mut self, rcc: &RegisterBlock, mode: HSEClockMode) {
+        // HSEBYP can only be written while the HSE is off
+        rcc.cr
+            .modify(|_, w| w.hsebyp().bit(mode == HSEClockMode::Bypass));
+        rcc.cr.modify(|_, w| w.hseon().set_bit());
+        while rcc.cr.read().hserdy().bit_is_clear() {}
+    }
+}
+
 impl<CS> Rtc<CS> {
     fn unlock(&mut self, rcc: &RegisterBlock, pwr: &mut PWR) {
         // Enable the backup interface
-        rcc.apb1enr.modify(|_, w| w.pwren().set_bit());
+        PWR::enable(rcc);
 
         pwr.cr.modify(|_, w| {
             w
@@ -338,6 +502,35 @@ impl<CS> Rtc<CS> {
         self.regs.wpr.write(|w| unsafe { w.bits(0xFF) });
     }
 
+    /// Arms the wakeup timer to fire once after `ticks` periods of RTCCLK / 16,
+    /// `ticks` must not be 0
+    ///
+    /// The wakeup flag only raises an event on EXTI line 20, so WFE wakes the core
+    /// from STOP mode without an interrupt handler.
+    pub(crate) fn arm_wakeup_event(&mut self, ticks: u16) {
+        let exti = unsafe { &(*EXTI::ptr()) };
+        self.regs.wpr.write(|w| unsafe { w.bits(0xCA) });
+        self.regs.wpr.write(|w| unsafe { w.bits(0x53) });
+        self.regs.cr.modify(|_, w| w.wute().clear_bit());
+        while self.regs.isr.read().wutwf().bit_is_clear() {}
+        self.regs.isr.modify(|_, w| w.wutf().clear_bit());
+        self.regs.wutr.write(|w| unsafe { w.wut().bits(ticks - 1) });
+        bb::set(&exti.rtsr, 20);
+        bb::set(&exti.emr, 20);
+        bb::set(&exti.pr, 20);
+        self.regs.cr.modify(|_, w| unsafe {
+            w.wucksel().bits(0b000);
+            w.wutie().set_bit();
+            w.wute().set_bit()
+        });
+        self.regs.wpr.write(|w| unsafe { w.bits(0xFF) });
+    }
+
+    /// Returns `true` once the armed wakeup timer has fired
+    pub(crate) fn is_wakeup_pending(&self) -> bool {
+        self.regs.isr.read().wutf().bit_is_set()
+    }
+
     /// As described in Section 27.3.7 in RM0316,
     /// this function is used to disable write protection
     /// when modifying an RTC register
@@ -5,9 +5,9 @@ use core::ptr;
 use crate::gpio::gpioa::{PA10, PA2, PA3, PA9};
 use crate::gpio::gpiob::{PB10, PB11};
 use crate::gpio::{AltMode, Floating, Input};
-use crate::rcc::Rcc;
+use crate::rcc::{self, BusClock, Clocks, Enable, Rcc};
 use crate::stm32::{USART1, USART2, USART3};
-use crate::time::Bps;
+use crate::time::{Bps, Hertz};
 use hal;
 use hal::prelude::*;
 use nb::block;
@@ -107,6 +107,14 @@ impl Config {
 #[derive(Debug)]
 pub struct InvalidConfig;
 
+/// Returns the BRR value for `baudrate` from the `pclk` bus clock
+fn brr(pclk: Hertz, baudrate: Bps) -> u32 {
+    let div = (pclk.0 * 25) / (4 * baudrate.0);
+    let mantissa = div / 100;
+    let fraction = ((div - mantissa * 100) * 16 + 50) / 100;
+    mantissa << 4 | fraction
+}
+
 impl Default for Config {
     fn default() -> Config {
         let baudrate = 19_200_u32.bps();
@@ -147,6 +155,7 @@ impl Pins<USART3> for (PB10<Input<Floating>>, PB11<Input<Floating>>) {
 /// Serial abstraction
 pub struct Serial<USART> {
     usart: USART,
+    baudrate: Bps,
     rx: Rx<USART>,
     tx: Tx<USART>,
 }
@@ -172,7 +181,7 @@ pub trait SerialExt<USART, PINS> {
 
 macro_rules! usart {
     ($(
-        $USARTX:ident: ($usartX:ident, $apbXenr:ident, $usartXen:ident, $pclkX:ident, $SerialExt:ident),
+        $USARTX:ident: ($usartX:ident, $SerialExt:ident),
     )+) => {
         $(
             impl<PINS> SerialExt<$USARTX, PINS> for $USARTX
@@ -197,15 +206,11 @@ macro_rules! usart {
                     pins.setup();
 
                     // Enable clock for USART
-                    rcc.rb.$apbXenr.modify(|_, w| w.$usartXen().set_bit());
+                    $USARTX::enable(rcc);
 
                     // Calculate correct baudrate divisor on the fly
-                    let div = (rcc.clocks.$pclkX().0 * 25) / (4 * config.baudrate.0);
-                    let mantissa = div / 100;
-                    let fraction = ((div - mantissa * 100) * 16 + 50) / 100;
-                    usart
-                        .brr
-                        .write(|w| unsafe { w.bits(mantissa << 4 | fraction) });
+                    let brr = brr($USARTX::clock(&rcc.clocks), config.baudrate);
+                    usart.brr.write(|w| unsafe { w.bits(brr) });
 
                     // Reset other registers to disable advanced USART features
                     usart.cr2.reset();
@@ -245,6 +250,7 @@ macro_rules! usart {
                     });
                     Ok(Serial {
                         usart,
+                        baudrate: config.baudrate,
                         tx: Tx { _usart: PhantomData },
                         rx: Rx { _usart: PhantomData },
                     })
@@ -300,12 +306,9 @@ macro_rules! usart {
                     self.usart.cr1.modify(|_, w| w.ue().clear_bit());
 
                     // Baud rate
-                    let div = (rcc.clocks.$pclkX().0 * 25) / (4 * config.baudrate.0);
-                    let mantissa = div / 100;
-                    let fraction = ((div - mantissa * 100) * 16 + 50) / 100;
-                    self.usart
-                        .brr
-                        .write(|w| unsafe { w.bits(mantissa << 4 | fraction) });
+                    let brr = brr($USARTX::clock(&rcc.clocks), config.baudrate);
+                    self.usart.brr.write(|w| unsafe { w.bits(brr) });
+                    self.baudrate = config.baudrate;
 
                     // Frame format (word length, parity) + re-enable
                     self.usart.cr1.modify(|_, w| {
@@ -339,10 +342,26 @@ macro_rules! usart {
                     });
                 }
 
+                /// Keeps the baud rate after the bus clock changed to `clocks`
+                ///
+                /// Waits for the current transmission to complete first.
+                pub fn set_clocks(&mut self, clocks: &Clocks) {
+                    while self.usart.sr.read().tc().bit_is_clear() {}
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    let brr = brr($USARTX::clock(clocks), self.baudrate);
+                    self.usart.brr.write(|w| unsafe { w.bits(brr) });
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit());
+                }
+
                 pub fn split(self) -> (Tx<$USARTX>, Rx<$USARTX>) {
                     (self.tx, self.rx)
                 }
 
+                /// Sets whether the USART clock runs in sleep mode, see [`rcc::set_sleep_clock`]
+                pub fn set_sleep_clock(&mut self, enabled: bool) {
+                    rcc::set_sleep_clock::<$USARTX>(enabled);
+                }
+
                 pub fn release(self) -> $USARTX {
                     self.usart
                 }
@@ -429,9 +448,9 @@ macro_rules! usart {
 }
 
 usart! {
-    USART1: (usart1, apb2enr, usart1en, apb2_clk, Serial1Ext),
-    USART2: (usart2, apb1enr, usart2en, apb1_clk, Serial2Ext),
-    USART3: (usart3, apb1enr, usart3en, apb1_clk, Serial3Ext),
+    USART1: (usart1, Serial1Ext),
+    USART2: (usart2, Serial2Ext),
+    USART3: (usart3, Serial3Ext),
 }
 
 impl<USART> fmt::Write for Serial<USART>
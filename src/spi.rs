@@ -2,7 +2,7 @@ use crate::gpio::gpioa::{PA11, PA12, PA5, PA6, PA7};
 use crate::gpio::gpiob::{PB13, PB14, PB15, PB3, PB4, PB5};
 use crate::gpio::gpioc::{PC10, PC11, PC12};
 use crate::gpio::{Floating, Input};
-use crate::rcc::Rcc;
+use crate::rcc::{self, BusClock, Clocks, Enable, Rcc};
 use crate::stm32::{SPI1, SPI2, SPI3};
 use crate::time::Hertz;
 use core::ptr;
@@ -115,6 +115,22 @@ pins! {
 pub struct Spi<SPI, PINS> {
     spi: SPI,
     pins: PINS,
+    freq: u32,
+}
+
+/// Returns the BR divider closest to `freq` from the `pclk` bus clock, without
+/// exceeding it, or the fastest one when the bus is too slow
+fn baud_rate(pclk: u32, freq: u32) -> u8 {
+    match pclk / freq {
+        0..=2 => 0b000,
+        3..=5 => 0b001,
+        6..=11 => 0b010,
+        12..=23 => 0b011,
+        24..=47 => 0b100,
+        48..=95 => 0b101,
+        96..=191 => 0b110,
+        _ => 0b111,
+    }
 }
 
 pub trait SpiExt<SPI>: Sized {
@@ -125,7 +141,7 @@ pub trait SpiExt<SPI>: Sized {
 }
 
 macro_rules! spi {
-    ($($SPIX:ident: ($spiX:ident, $apbXenr:ident, $spiXen:ident, $pclkX:ident),)+) => {
+    ($($SPIX:ident: $spiX:ident,)+) => {
         $(
             impl<PINS> Spi<$SPIX, PINS> {
                 pub fn $spiX<T>(
@@ -140,24 +156,13 @@ macro_rules! spi {
                 T: Into<Hertz>
                 {
                     // Enable clock for SPI
-                    rcc.rb.$apbXenr.modify(|_, w| w.$spiXen().set_bit());
+                    $SPIX::enable(rcc);
 
                     // disable SS output
                     spi.cr2.modify(|_, w| w.ssoe().clear_bit());
 
-                    let spi_freq = freq.into().0;
-                    let apb_freq = rcc.clocks.$pclkX().0;
-                    let br = match apb_freq / spi_freq {
-                        0 => unreachable!(),
-                        1..=2 => 0b000,
-                        3..=5 => 0b001,
-                        6..=11 => 0b010,
-                        12..=23 => 0b011,
-                        24..=47 => 0b100,
-                        48..=95 => 0b101,
-                        96..=191 => 0b110,
-                        _ => 0b111,
-                    };
+                    let freq = freq.into().0;
+                    let br = baud_rate($SPIX::clock(&rcc.clocks).0, freq);
 
                     // mstr: master configuration
                     // lsbfirst: MSB first
@@ -191,7 +196,25 @@ macro_rules! spi {
                             .spe()
                             .set_bit()
                     });
-                    Spi { spi, pins }
+                    Spi { spi, pins, freq }
+                }
+
+                /// Keeps the SPI clock rate after the bus clock changed to `clocks`
+                ///
+                /// Waits for the current transfer to complete first.
+                pub fn set_clocks(&mut self, clocks: &Clocks) {
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+                    let br = baud_rate($SPIX::clock(clocks).0, self.freq);
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    #[allow(unused_unsafe)]
+                    self.spi
+                        .cr1
+                        .modify(|_, w| unsafe { w.br().bits(br).spe().set_bit() });
+                }
+
+                /// Sets whether the SPI clock runs in sleep mode, see [`rcc::set_sleep_clock`]
+                pub fn set_sleep_clock(&mut self, enabled: bool) {
+                    rcc::set_sleep_clock::<$SPIX>(enabled);
                 }
 
                 pub fn free(self) -> ($SPIX, PINS) {
@@ -264,7 +287,7 @@ macro_rules! spi {
 }
 
 spi! {
-    SPI1: (spi1, apb2enr, spi1en, apb2_clk),
-    SPI2: (spi2, apb1enr, spi2en, apb1_clk),
-    SPI3: (spi3, apb1enr, spi3en, apb1_clk),
+    SPI1: spi1,
+    SPI2: spi2,
+    SPI3: spi3,
 }
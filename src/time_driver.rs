@@ -0,0 +1,214 @@
+//! Embassy time driver
+//!
+//! Selected with one of the `time-driver-tim2`, `time-driver-tim3` or
+//! `time-driver-tim5` features. The driver ticks at `embassy_time_driver::TICK_HZ`,
+//! chosen with the `tick-hz-*` features of `embassy-time`, which has to divide the
+//! timer clock with a 16-bit prescaler.
+//!
+//! The counter is extended to 64 bits like in [`crate::monotonic`]: the update
+//! event and CC2 at the half period count half periods, CC1 is the alarm. The
+//! driver defines the timer interrupt handler itself.
+//!
+//! The prescaler is computed once in [`init`] from the timer clock, so the bus
+//! clocks must not change through [`crate::rcc::Rcc::set_config`] afterwards:
+//! the tick rate would no longer be `TICK_HZ`.
+use core::cell::{Cell, RefCell};
+use core::sync::atomic::{compiler_fence, AtomicU32, Ordering};
+use core::task::Waker;
+
+use critical_section::{CriticalSection, Mutex};
+use embassy_time_driver::{Driver, TICK_HZ};
+use embassy_time_queue_utils::Queue;
+
+use crate::rcc::{self, BusTimerClock, Enable, Rcc, Reset};
+use crate::stm32::{interrupt, Interrupt, NVIC};
+use crate::timer::Error;
+
+#[cfg(any(
+    all(feature = "time-driver-tim2", feature = "time-driver-tim3"),
+    all(feature = "time-driver-tim2", feature = "time-driver-tim5"),
+    all(feature = "time-driver-tim3", feature = "time-driver-tim5"),
+))]
+compile_error!("only one `time-driver-*` feature can be enabled");
+
+#[cfg(all(feature = "time-driver-tim5", not(feature = "_cat3plus")))]
+compile_error!("`time-driver-tim5` needs a part with TIM5: enable `stm32l162` or one of the `cat3` to `cat6` features");
+
+#[cfg(feature = "time-driver-tim2")]
+use crate::stm32::TIM2 as TIM;
+#[cfg(feature = "time-driver-tim3")]
+use crate::stm32::TIM3 as TIM;
+#[cfg(feature = "time-driver-tim5")]
+use crate::stm32::TIM5 as TIM;
+
+#[cfg(not(feature = "time-driver-tim5"))]
+const BITS: u32 = 16;
+#[cfg(feature = "time-driver-tim5")]
+const BITS: u32 = 32;
+
+const HALF: u32 = 1 << (BITS - 1);
+const MAX: u32 = u32::MAX >> (32 - BITS);
+
+const UIF: u32 = 1 << 0;
+const CC1IF: u32 = 1 << 1;
+const CC2IF: u32 = 1 << 2;
+
+/// Starts the time driver on `tim`
+///
+/// Call once after `Rcc::freeze`, before any embassy timer is used.
+pub fn init(tim: TIM, rcc: &mut Rcc) -> Result<(), Error> {
+    let clk = u64::from(TIM::timer_clock(&rcc.clocks).0);
+    if !clk.is_multiple_of(TICK_HZ) || clk / TICK_HZ > 1 << 16 {
+        return Err(Error::InvalidTickRate);
+    }
+
+    TIM::enable(rcc);
+    TIM::reset(rcc);
+    rcc::set_sleep_clock::<TIM>(true);
+
+    tim.psc.write(|w| w.psc().bits((clk / TICK_HZ - 1) as u16));
+    tim.arr.write(|w| unsafe { w.bits(MAX) });
+    tim.ccr2().write(|w| unsafe { w.bits(HALF) });
+    // load the prescaler without raising an update interrupt
+    tim.cr1.modify(|_, w| w.urs().set_bit());
+    tim.egr.write(|w| w.ug().set_bit());
+    tim.sr.write(|w| unsafe { w.bits(0) });
+    tim.dier.write(|w| w.uie().set_bit().cc2ie().set_bit());
+    tim.cr1.modify(|_, w| w.cen().set_bit());
+
+    unsafe { NVIC::unmask(IRQ) };
+    Ok(())
+}
+
+#[cfg(feature = "time-driver-tim2")]
+const IRQ: Interrupt = Interrupt::TIM2;
+#[cfg(feature = "time-driver-tim3")]
+const IRQ: Interrupt = Interrupt::TIM3;
+#[cfg(feature = "time-driver-tim5")]
+const IRQ: Interrupt = Interrupt::TIM5;
+
+#[cfg(feature = "time-driver-tim2")]
+#[interrupt]
+fn TIM2() {
+    DRIVER.on_interrupt();
+}
+
+#[cfg(feature = "time-driver-tim3")]
+#[interrupt]
+fn TIM3() {
+    DRIVER.on_interrupt();
+}
+
+#[cfg(feature = "time-driver-tim5")]
+#[interrupt]
+fn TIM5() {
+    DRIVER.on_interrupt();
+}
+
+fn regs() -> &'static crate::stm32::tim2::RegisterBlock {
+    unsafe { &*TIM::ptr() }
+}
+
+struct TimerDriver {
+    /// Number of half periods elapsed
+    period: AtomicU32,
+    /// Timestamp of the armed alarm, `u64::MAX` when none is
+    alarm: Mutex<Cell<u64>>,
+    queue: Mutex<RefCell<Queue>>,
+}
+
+embassy_time_driver::time_driver_impl!(static DRIVER: TimerDriver = TimerDriver {
+    period: AtomicU32::new(0),
+    alarm: Mutex::new(Cell::new(u64::MAX)),
+    queue: Mutex::new(RefCell::new(Queue::new())),
+});
+
+impl TimerDriver {
+    fn on_interrupt(&self) {
+        let r = regs();
+        critical_section::with(|cs| {
+            let sr = r.sr.read().bits();
+            let dier = r.dier.read().bits();
+            r.sr.write(|w| unsafe { w.bits(!(sr & (UIF | CC1IF | CC2IF))) });
+
+            if sr & UIF != 0 {
+                self.next_period(cs);
+            }
+            if sr & CC2IF != 0 {
+                self.next_period(cs);
+            }
+            if sr & dier & CC1IF != 0 {
+                self.trigger_alarm(cs);
+            }
+        })
+    }
+
+    fn next_period(&self, cs: CriticalSection) {
+        let period = self.period.load(Ordering::Relaxed).wrapping_add(1);
+        self.period.store(period, Ordering::Relaxed);
+        let start = u64::from(period) << (BITS - 1);
+
+        // the compare is already set, it only has to be enabled once the alarm is
+        // less than one counter period away
+        if self.alarm.borrow(cs).get() < start + u64::from(HALF + HALF / 2) {
+            regs().dier.modify(|_, w| w.cc1ie().set_bit());
+        }
+    }
+
+    fn trigger_alarm(&self, cs: CriticalSection) {
+        let mut queue = self.queue.borrow(cs).borrow_mut();
+        let mut next = queue.next_expiration(self.now());
+        while !self.set_alarm(cs, next) {
+            next = queue.next_expiration(self.now());
+        }
+    }
+
+    /// Arms the alarm, returns `false` if `at` has already passed
+    fn set_alarm(&self, cs: CriticalSection, at: u64) -> bool {
+        let r = regs();
+        self.alarm.borrow(cs).set(at);
+
+        let now = self.now();
+        if at <= now {
+            self.alarm.borrow(cs).set(u64::MAX);
+            r.dier.modify(|_, w| w.cc1ie().clear_bit());
+            return false;
+        }
+
+        r.ccr1().write(|w| unsafe { w.bits(at as u32 & MAX) });
+        // alarms further away are enabled by `next_period`
+        let near = at - now < u64::from(HALF + HALF / 2);
+        r.dier.modify(|_, w| w.cc1ie().bit(near));
+
+        // the counter may have passed the compare while it was written
+        if at <= self.now() {
+            self.alarm.borrow(cs).set(u64::MAX);
+            r.dier.modify(|_, w| w.cc1ie().clear_bit());
+            return false;
+        }
+        true
+    }
+}
+
+impl Driver for TimerDriver {
+    fn now(&self) -> u64 {
+        let period = self.period.load(Ordering::Relaxed);
+        compiler_fence(Ordering::Acquire);
+        let counter = regs().cnt.read().bits() & MAX;
+        // the counter is in the first half on even periods and in the second half
+        // on odd ones, the XOR catches up a pending half period
+        (u64::from(period) << (BITS - 1)) + u64::from(counter ^ ((period & 1) << (BITS - 1)))
+    }
+
+    fn schedule_wake(&self, at: u64, waker: &Waker) {
+        critical_section::with(|cs| {
+            let mut queue = self.queue.borrow(cs).borrow_mut();
+            if queue.schedule_wake(at, waker) {
+                let mut next = queue.next_expiration(self.now());
+                while !self.set_alarm(cs, next) {
+                    next = queue.next_expiration(self.now());
+                }
+            }
+        })
+    }
+}
@@ -1,14 +1,253 @@
 //! Timers
 use cast::{u16, u32};
+use cortex_m::asm;
 use cortex_m::peripheral::syst::SystClkSource;
-use cortex_m::peripheral::SYST;
+use cortex_m::peripheral::{NVIC, SCB, SYST};
+use embedded_hal_1::delay::DelayNs;
+use fugit::{NanosDurationU64, TimerDurationU32, TimerInstantU32};
+use hal::blocking::delay::{DelayMs, DelayUs};
 use hal::timer::{CountDown, Periodic};
 use nb;
 use void::Void;
 
-use crate::rcc::{Clocks, Rcc};
-use crate::stm32::{TIM2, TIM3, TIM4, TIM5, TIM6, TIM7, TIM9};
-use crate::time::Hertz;
+use crate::rcc::{self, BusTimerClock, Clocks, Enable, Rcc, Reset};
+#[cfg(feature = "_cat3plus")]
+use crate::stm32::TIM5;
+use crate::stm32::{Interrupt, TIM10, TIM11, TIM2, TIM3, TIM4, TIM6, TIM7, TIM9};
+use crate::time::{Hertz, MicroSeconds};
+
+/// Timer error
+#[derive(Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The period is shorter than two timer clock ticks
+    PeriodTooShort,
+    /// The period does not fit into the 16-bit prescaler and auto-reload registers
+    PeriodTooLong,
+    /// The timer clock cannot be divided down to the requested tick rate
+    InvalidTickRate,
+    /// The timer is not running
+    Disabled,
+}
+
+/// Timer interrupt and status events
+///
+/// The events share their bit positions in DIER and SR on every timer. Events a
+/// timer does not have, such as the capture/compare events of TIM6 and TIM7, are
+/// ignored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// Counter overflow or underflow, or update generated by software or a slave reset
+    Update,
+    /// Capture/compare channel 1
+    CC1,
+    /// Capture/compare channel 2
+    CC2,
+    /// Capture/compare channel 3
+    CC3,
+    /// Capture/compare channel 4
+    CC4,
+    /// Trigger input of the slave mode controller
+    Trigger,
+}
+
+impl Event {
+    const fn mask(self) -> u32 {
+        match self {
+            Event::Update => 1 << 0,
+            Event::CC1 => 1 << 1,
+            Event::CC2 => 1 << 2,
+            Event::CC3 => 1 << 3,
+            Event::CC4 => 1 << 4,
+            Event::Trigger => 1 << 6,
+        }
+    }
+}
+
+/// Sources that raise the update interrupt
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpdateSource {
+    /// Counter overflow, the UG bit and slave mode resets
+    Any,
+    /// Counter overflow or underflow only
+    Overflow,
+}
+
+/// Fewest timer clock ticks in one period of a 16-bit timer
+const MIN_TICKS: u64 = 2;
+/// Most timer clock ticks in one period of a 16-bit timer, 2^16 prescaled ticks
+/// of 2^16 clock ticks each
+const MAX_TICKS: u64 = 1 << 32;
+
+/// A timer period, given either as a duration or as a rate
+pub trait Period {
+    /// Returns the number of `clk` ticks in one period, rounded to the nearest tick
+    fn clock_ticks(&self, clk: Hertz) -> u64;
+}
+
+impl<const NOM: u32, const DENOM: u32> Period for fugit::Duration<u32, NOM, DENOM> {
+    fn clock_ticks(&self, clk: Hertz) -> u64 {
+        duration_ticks(clk, u64::from(self.ticks()), NOM, DENOM)
+    }
+}
+
+impl<const NOM: u32, const DENOM: u32> Period for fugit::Duration<u64, NOM, DENOM> {
+    fn clock_ticks(&self, clk: Hertz) -> u64 {
+        duration_ticks(clk, self.ticks(), NOM, DENOM)
+    }
+}
+
+impl<const NOM: u32, const DENOM: u32> Period for fugit::Rate<u32, NOM, DENOM> {
+    fn clock_ticks(&self, clk: Hertz) -> u64 {
+        let hz = u128::from(self.raw()) * u128::from(NOM);
+        if hz == 0 {
+            return u64::MAX;
+        }
+        let ticks = u128::from(clk.0) * u128::from(DENOM);
+        saturate((ticks + hz / 2) / hz)
+    }
+}
+
+impl Period for Hertz {
+    fn clock_ticks(&self, clk: Hertz) -> u64 {
+        fugit::HertzU32::from_raw(self.0).clock_ticks(clk)
+    }
+}
+
+impl Period for MicroSeconds {
+    fn clock_ticks(&self, clk: Hertz) -> u64 {
+        fugit::MicrosDurationU32::from_ticks(self.0).clock_ticks(clk)
+    }
+}
+
+fn duration_ticks(clk: Hertz, ticks: u64, nom: u32, denom: u32) -> u64 {
+    let ticks = u128::from(clk.0) * u128::from(ticks) * u128::from(nom);
+    let denom = u128::from(denom);
+    saturate((ticks + denom / 2) / denom)
+}
+
+fn saturate(ticks: u128) -> u64 {
+    if ticks > u128::from(u64::MAX) {
+        u64::MAX
+    } else {
+        ticks as u64
+    }
+}
+
+/// Finds the prescaler and auto-reload values for a period of `ticks` timer clocks
+///
+/// The smallest prescaler that fits is used, as it gives the finest resolution, and
+/// the auto-reload value is rounded to the nearest prescaled tick.
+pub(crate) fn psc_arr(ticks: u64) -> Result<(u16, u16), Error> {
+    if ticks < MIN_TICKS {
+        return Err(Error::PeriodTooShort);
+    }
+    if ticks > MAX_TICKS {
+        return Err(Error::PeriodTooLong);
+    }
+    let div = ((ticks - 1) >> 16) + 1;
+    let arr = (ticks + div / 2) / div - 1;
+    Ok(((div - 1) as u16, arr as u16))
+}
+
+/// Finds the prescaler that divides `clk` down to exactly `freq` ticks per second
+fn tick_psc(clk: Hertz, freq: u32) -> Result<u16, Error> {
+    if freq == 0 || !clk.0.is_multiple_of(freq) {
+        return Err(Error::InvalidTickRate);
+    }
+    u16(clk.0 / freq - 1).map_err(|_| Error::InvalidTickRate)
+}
+
+/// Returns the period of a timer running with `psc` and `arr` from `clk`
+fn achieved_period(clk: Hertz, psc: u16, arr: u16) -> NanosDurationU64 {
+    let ticks = (u64::from(psc) + 1) * (u64::from(arr) + 1);
+    NanosDurationU64::from_ticks(ticks * 1_000_000_000 / u64::from(clk.0))
+}
+
+/// Master mode selection, the signal a timer drives on its trigger output (TRGO)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MasterMode {
+    /// The UG bit of the EGR register is used as trigger output
+    Reset = 0b000,
+    /// The counter enable signal is used as trigger output
+    Enable = 0b001,
+    /// The update event is used as trigger output
+    Update = 0b010,
+    /// A pulse is sent when CC1IF is set
+    ComparePulse = 0b011,
+    /// OC1REF is used as trigger output
+    Compare1 = 0b100,
+    /// OC2REF is used as trigger output
+    Compare2 = 0b101,
+    /// OC3REF is used as trigger output
+    Compare3 = 0b110,
+    /// OC4REF is used as trigger output
+    Compare4 = 0b111,
+}
+
+/// Master mode selection of the basic timers TIM6 and TIM7, which have no
+/// capture/compare channels
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BasicMasterMode {
+    /// The UG bit of the EGR register is used as trigger output
+    Reset = 0b000,
+    /// The counter enable signal is used as trigger output
+    Enable = 0b001,
+    /// The update event is used as trigger output
+    Update = 0b010,
+}
+
+/// Slave mode selection, how a timer reacts to its trigger input (TRGI)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlaveMode {
+    /// The prescaler is clocked directly by the internal clock
+    Disabled = 0b000,
+    /// A rising edge of the trigger reinitializes the counter
+    Reset = 0b100,
+    /// The counter runs while the trigger is high
+    Gated = 0b101,
+    /// A rising edge of the trigger starts the counter
+    Trigger = 0b110,
+    /// Rising edges of the trigger clock the counter
+    ExternalClock = 0b111,
+}
+
+/// Internal trigger connection (ITRx) from timer `MASTER` to this timer
+///
+/// Only the master/slave pairs that are wired together on the STM32L1 implement
+/// this trait (RM0038, "TIMx internal trigger connection"), so an invalid
+/// synchronization is rejected at compile time. TIM10 and TIM11 drive the
+/// trigger with their OC1REF signal, the other masters with their TRGO output.
+pub trait InternalTrigger<MASTER> {
+    /// Trigger selection (TS) value routing `MASTER` to this timer
+    const TS: u8;
+}
+
+macro_rules! internal_triggers {
+    ($($TIM:ident: [$($MASTER:ident: $ts:expr),+],)+) => {
+        $(
+            $(
+                impl InternalTrigger<$MASTER> for $TIM {
+                    const TS: u8 = $ts;
+                }
+            )+
+        )+
+    }
+}
+
+internal_triggers! {
+    TIM2: [TIM10: 0b000, TIM3: 0b010, TIM4: 0b011],
+    TIM3: [TIM9: 0b000, TIM2: 0b001, TIM4: 0b011],
+    TIM4: [TIM10: 0b000, TIM2: 0b001, TIM3: 0b010, TIM9: 0b011],
+    TIM9: [TIM2: 0b000, TIM3: 0b001, TIM10: 0b010, TIM11: 0b011],
+}
+
+#[cfg(feature = "_cat3plus")]
+internal_triggers! {
+    TIM2: [TIM5: 0b001],
+    TIM3: [TIM5: 0b010],
+    TIM5: [TIM2: 0b000, TIM3: 0b001, TIM4: 0b010, TIM9: 0b011],
+}
 
 pub trait TimerExt<TIM> {
     fn timer<T>(self, timeout: T, rcc: &mut Rcc) -> Timer<TIM>
@@ -16,6 +255,17 @@ pub trait TimerExt<TIM> {
         T: Into<Hertz>;
 }
 
+pub trait TimerPeriodExt<TIM>: Sized {
+    /// Configures the timer as a periodic count down timer with `period`, given
+    /// as a fugit duration or rate
+    ///
+    /// Fails if the period does not fit the prescaler and auto-reload registers,
+    /// see [`Timer::period`] for the period actually achieved.
+    fn timer_period<P>(self, period: P, rcc: &mut Rcc) -> Result<Timer<TIM>, Error>
+    where
+        P: Period;
+}
+
 /// Hardware timers
 pub struct Timer<TIM> {
     clocks: Clocks,
@@ -37,6 +287,17 @@ impl Timer<SYST> {
         timer
     }
 
+    /// Keeps the timeout after the system clock changed to `clocks`
+    pub fn set_clocks(&mut self, clocks: &Clocks) {
+        let cycles = u64::from(SYST::get_reload()) + 1;
+        let rvr = cycles * u64::from(clocks.sys_clk().0) / u64::from(self.clocks.sys_clk().0) - 1;
+        assert!(rvr < (1 << 24));
+
+        self.tim.set_reload(rvr as u32);
+        self.tim.clear_current();
+        self.clocks = *clocks;
+    }
+
     /// Starts listening
     pub fn listen(&mut self) {
         self.tim.enable_interrupt()
@@ -83,8 +344,22 @@ impl TimerExt<SYST> for SYST {
 
 impl Periodic for Timer<SYST> {}
 
+pub trait DelayExt<TIM> {
+    fn delay(self, rcc: &Rcc) -> Delay<TIM>;
+}
+
+/// General-purpose or basic timer as a delay provider
+///
+/// The timer runs in one-pulse mode, delays longer than one timer period are
+/// chained from several pulses.
+pub struct Delay<TIM> {
+    clk: Hertz,
+    tim: TIM,
+    sleep: bool,
+}
+
 macro_rules! timers {
-    ($($TIM:ident: ($tim:ident, $timXen:ident, $timXrst:ident, $apbenr:ident, $apbrstr:ident, $timclk:ident),)+) => {
+    ($($TIM:ident: ($tim:ident, $flags:expr),)+) => {
         $(
             impl TimerExt<$TIM> for $TIM {
                 fn timer<T>(self, timeout: T, rcc: &mut Rcc) -> Timer<$TIM>
@@ -95,15 +370,33 @@ macro_rules! timers {
                 }
             }
 
+            impl TimerPeriodExt<$TIM> for $TIM {
+                fn timer_period<P>(self, period: P, rcc: &mut Rcc) -> Result<Timer<$TIM>, Error>
+                where
+                    P: Period,
+                {
+                    let (psc, arr) = psc_arr(period.clock_ticks($TIM::timer_clock(&rcc.clocks)))?;
+
+                    $TIM::enable(rcc);
+                    $TIM::reset(rcc);
+
+                    let mut timer = Timer {
+                        tim: self,
+                        clocks: rcc.clocks,
+                    };
+                    timer.write_period(psc, arr);
+                    Ok(timer)
+                }
+            }
+
             impl Timer<$TIM> {
                 /// Configures a TIM peripheral as a periodic count down timer
                 pub fn $tim<T>(tim: $TIM, timeout: T, rcc: &mut Rcc) -> Self
                 where
                     T: Into<Hertz>,
                 {
-                    rcc.rb.$apbenr.modify(|_, w| w.$timXen().set_bit());
-                    rcc.rb.$apbrstr.modify(|_, w| w.$timXrst().set_bit());
-                    rcc.rb.$apbrstr.modify(|_, w| w.$timXrst().clear_bit());
+                    $TIM::enable(rcc);
+                    $TIM::reset(rcc);
 
                     let mut timer = Timer {
                         tim,
@@ -113,19 +406,90 @@ macro_rules! timers {
                     timer
                 }
 
-                /// Starts listening
-                pub fn listen(&mut self) {
-                    self.tim.dier.modify(|_, w| w.uie().set_bit());
+                /// Starts listening for `event`
+                pub fn listen(&mut self, event: Event) {
+                    let mask = event.mask() & $flags;
+                    self.tim
+                        .dier
+                        .modify(|r, w| unsafe { w.bits(r.bits() | mask) });
                 }
 
-                /// Stops listening
-                pub fn unlisten(&mut self) {
-                    self.tim.dier.modify(|_, w| w.uie().clear_bit());
+                /// Stops listening for `event`
+                pub fn unlisten(&mut self, event: Event) {
+                    self.tim
+                        .dier
+                        .modify(|r, w| unsafe { w.bits(r.bits() & !event.mask()) });
+                }
+
+                /// Returns `true` if the flag of `event` is set
+                pub fn is_pending(&self, event: Event) -> bool {
+                    self.tim.sr.read().bits() & event.mask() != 0
                 }
 
-                /// Clears interrupt flag
+                /// Clears the flag of `event`
+                pub fn clear(&mut self, event: Event) {
+                    // the flags are cleared by writing 0, writing 1 leaves them untouched;
+                    // reserved bits and flags this timer lacks are written as 0
+                    self.tim
+                        .sr
+                        .write(|w| unsafe { w.bits(!event.mask() & $flags) });
+                }
+
+                /// Clears the update interrupt flag
                 pub fn clear_irq(&mut self) {
-                    self.tim.sr.modify(|_, w| w.uif().clear_bit());
+                    self.clear(Event::Update);
+                }
+
+                /// Returns the current counter value
+                pub fn counter(&self) -> u32 {
+                    self.tim.cnt.read().bits()
+                }
+
+                /// Sets the counter value
+                pub fn set_counter(&mut self, count: u32) {
+                    self.tim.cnt.write(|w| unsafe { w.bits(count) });
+                }
+
+                /// Returns the prescaler, the timer clock is divided by `prescaler + 1`
+                pub fn prescaler(&self) -> u16 {
+                    self.tim.psc.read().psc().bits()
+                }
+
+                /// Sets the prescaler, it is loaded at the next update event
+                pub fn set_prescaler(&mut self, psc: u16) {
+                    self.tim.psc.write(|w| w.psc().bits(psc));
+                }
+
+                /// Stops the counter, keeping its value
+                pub fn pause(&mut self) {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                }
+
+                /// Restarts a paused counter
+                pub fn resume(&mut self) {
+                    self.tim.cr1.modify(|_, w| w.cen().set_bit());
+                }
+
+                /// Selects which sources raise the update interrupt (URS)
+                ///
+                /// Restarting the timer selects [`UpdateSource::Overflow`] again.
+                pub fn set_update_source(&mut self, source: UpdateSource) {
+                    self.tim
+                        .cr1
+                        .modify(|_, w| w.urs().bit(source == UpdateSource::Overflow));
+                }
+
+                /// Disables or enables the update event (UDIS)
+                ///
+                /// While disabled the prescaler and auto-reload registers keep their
+                /// preloaded values and overflows raise no update interrupt.
+                pub fn set_update_disabled(&mut self, disabled: bool) {
+                    self.tim.cr1.modify(|_, w| w.udis().bit(disabled));
+                }
+
+                /// Sets whether the timer clock runs in sleep mode, see [`rcc::set_sleep_clock`]
+                pub fn set_sleep_clock(&mut self, enabled: bool) {
+                    rcc::set_sleep_clock::<$TIM>(enabled);
                 }
 
                 /// Releases the TIM peripheral
@@ -133,52 +497,510 @@ macro_rules! timers {
                     self.tim.cr1.modify(|_, w| w.cen().clear_bit());
                     self.tim
                 }
-            }
-
-            impl CountDown for Timer<$TIM> {
-                type Time = Hertz;
 
-                fn start<T>(&mut self, timeout: T)
+                /// Restarts the timer with `period`, given as a fugit duration or rate
+                ///
+                /// Returns the period the timer actually runs at, which differs from
+                /// the requested one by at most half a prescaled timer tick.
+                pub fn start_period<P>(&mut self, period: P) -> Result<NanosDurationU64, Error>
                 where
-                    T: Into<Hertz>,
+                    P: Period,
                 {
+                    let clk = self.tim_clk();
+                    let (psc, arr) = psc_arr(period.clock_ticks(clk))?;
+                    self.write_period(psc, arr);
+                    Ok(achieved_period(clk, psc, arr))
+                }
+
+                /// Returns the period the timer runs at
+                pub fn period(&self) -> NanosDurationU64 {
+                    let psc = self.tim.psc.read().psc().bits();
+                    let arr = self.tim.arr.read().bits() as u16;
+                    achieved_period(self.tim_clk(), psc, arr)
+                }
+
+                /// Keeps the period after the timer clock changed to `clocks`
+                ///
+                /// Only the prescaler is changed when it scales exactly, so the tick
+                /// rate set through the fugit timer is kept. Otherwise the period is
+                /// split again. The counter restarts from zero, a paused timer stays
+                /// paused.
+                pub fn set_clocks(&mut self, clocks: &Clocks) -> Result<(), Error> {
+                    let old = u64::from(self.tim_clk().0);
+                    let new = u64::from($TIM::timer_clock(clocks).0);
+                    let psc = u64::from(self.tim.psc.read().psc().bits()) + 1;
+                    let arr = u64::from(self.tim.arr.read().bits() as u16);
+                    let running = self.tim.cr1.read().cen().bit_is_set();
+
+                    let scaled = psc * new / old;
+                    let (psc, arr) =
+                        if (psc * new).is_multiple_of(old) && (1..=1 << 16).contains(&scaled) {
+                            ((scaled - 1) as u16, arr as u16)
+                        } else {
+                            psc_arr(psc * (arr + 1) * new / old)?
+                        };
+                    self.clocks = *clocks;
+                    self.write_period(psc, arr);
+                    if !running {
+                        self.pause();
+                    }
+                    Ok(())
+                }
+
+                fn write_period(&mut self, psc: u16, arr: u16) {
                     // pause
                     self.tim.cr1.modify(|_, w| w.cen().clear_bit());
                     // reset counter
                     self.tim.cnt.reset();
 
-                    let freq = timeout.into().0;
-                    let ticks = self.clocks.$timclk().0 / freq;
-                    let psc = u16((ticks - 1) / (1 << 16)).unwrap();
-
-                    self.tim.psc.write(|w| w.psc().bits(psc) );
-                    self.tim.arr.write(|w| unsafe { w.bits(ticks / u32(psc + 1)) });
+                    self.tim.psc.write(|w| w.psc().bits(psc));
+                    self.tim.arr.write(|w| unsafe { w.bits(u32(arr)) });
 
+                    // load the prescaler without raising an update interrupt
                     self.tim.cr1.modify(|_, w| w.urs().set_bit());
+                    self.tim.egr.write(|w| w.ug().set_bit());
                     self.tim.cr1.modify(|_, w| w.cen().set_bit());
                 }
 
+                fn tim_clk(&self) -> Hertz {
+                    $TIM::timer_clock(&self.clocks)
+                }
+            }
+
+            impl CountDown for Timer<$TIM> {
+                type Time = Hertz;
+
+                /// Starts the timer with the `timeout` rate
+                ///
+                /// Rates outside of the timer range are clamped to the closest
+                /// supported period, use [`Timer::start_period`] to get an error instead.
+                fn start<T>(&mut self, timeout: T)
+                where
+                    T: Into<Hertz>,
+                {
+                    let ticks = timeout.into().clock_ticks(self.tim_clk());
+                    let (psc, arr) = psc_arr(ticks.clamp(MIN_TICKS, MAX_TICKS)).unwrap_or_default();
+                    self.write_period(psc, arr);
+                }
+
                 fn wait(&mut self) -> nb::Result<(), Void> {
                     if self.tim.sr.read().uif().bit_is_clear() {
                         Err(nb::Error::WouldBlock)
                     } else {
-                        self.tim.sr.modify(|_, w| w.uif().clear_bit());
+                        self.clear(Event::Update);
                         Ok(())
                     }
                 }
             }
 
             impl Periodic for Timer<$TIM> {}
+
+            impl<const FREQ: u32> fugit_timer::Timer<FREQ> for Timer<$TIM> {
+                type Error = Error;
+
+                fn now(&mut self) -> TimerInstantU32<FREQ> {
+                    TimerInstantU32::from_ticks(self.tim.cnt.read().bits())
+                }
+
+                fn start(&mut self, duration: TimerDurationU32<FREQ>) -> Result<(), Error> {
+                    let psc = tick_psc(self.tim_clk(), FREQ)?;
+                    let arr = match duration.ticks() {
+                        0..=1 => return Err(Error::PeriodTooShort),
+                        ticks => u16(ticks - 1).map_err(|_| Error::PeriodTooLong)?,
+                    };
+                    self.write_period(psc, arr);
+                    Ok(())
+                }
+
+                fn cancel(&mut self) -> Result<(), Error> {
+                    if self.tim.cr1.read().cen().bit_is_clear() {
+                        return Err(Error::Disabled);
+                    }
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    Ok(())
+                }
+
+                fn wait(&mut self) -> nb::Result<(), Error> {
+                    if self.tim.sr.read().uif().bit_is_clear() {
+                        Err(nb::Error::WouldBlock)
+                    } else {
+                        self.clear(Event::Update);
+                        Ok(())
+                    }
+                }
+            }
+
+            impl DelayExt<$TIM> for $TIM {
+                fn delay(self, rcc: &Rcc) -> Delay<$TIM> {
+                    Delay::$tim(self, rcc)
+                }
+            }
+
+            impl Delay<$TIM> {
+                /// Configures a TIM peripheral as a delay provider
+                pub fn $tim(tim: $TIM, rcc: &Rcc) -> Self {
+                    $TIM::enable(rcc);
+                    $TIM::reset(rcc);
+
+                    // URS: only counter overflows raise the update flag
+                    tim.cr1.modify(|_, w| w.opm().set_bit().urs().set_bit());
+                    Delay {
+                        clk: $TIM::timer_clock(&rcc.clocks),
+                        tim,
+                        sleep: false,
+                    }
+                }
+
+                /// Keeps the delays accurate after the timer clock changed to `clocks`
+                pub fn set_clocks(&mut self, clocks: &Clocks) {
+                    self.clk = $TIM::timer_clock(clocks);
+                }
+
+                /// Sleeps with WFE while the timer runs instead of polling it
+                ///
+                /// The update interrupt is only pended, never taken, so the timer
+                /// interrupt has to stay masked in the NVIC. SEVONPEND lets the pending
+                /// interrupt wake the core from SLEEP mode. The timer clock keeps
+                /// running in sleep mode.
+                pub fn with_sleep(mut self, scb: &mut SCB) -> Self {
+                    rcc::set_sleep_clock::<$TIM>(true);
+                    scb.set_sevonpend();
+                    self.tim.dier.modify(|_, w| w.uie().set_bit());
+                    self.sleep = true;
+                    self
+                }
+
+                /// Waits for at least `ticks` timer clock cycles
+                fn wait_ticks(&mut self, ticks: u64) {
+                    // one prescaler for all pulses, so chained pulses keep the resolution
+                    let div = (ticks.saturating_sub(1) >> 16).min(0xFFFF) + 1;
+                    let mut cycles = ticks.div_ceil(div);
+
+                    self.tim.psc.write(|w| w.psc().bits((div - 1) as u16));
+                    self.tim.egr.write(|w| w.ug().set_bit());
+
+                    while cycles > 0 {
+                        let pulse = cycles.min(1 << 16);
+                        // the counter does not run with a zero auto-reload value
+                        let arr = pulse.max(2) - 1;
+                        #[allow(unused_unsafe)]
+                        self.tim.arr.write(|w| unsafe { w.bits(arr as u32) });
+                        self.tim.cnt.reset();
+                        self.clear_update();
+                        self.tim.cr1.modify(|_, w| w.cen().set_bit());
+                        while self.tim.sr.read().uif().bit_is_clear() {
+                            if self.sleep {
+                                asm::wfe();
+                            }
+                        }
+                        cycles -= pulse;
+                    }
+                    self.clear_update();
+                }
+
+                fn clear_update(&mut self) {
+                    // a read-modify-write could clear flags set in between
+                    self.tim
+                        .sr
+                        .write(|w| unsafe { w.bits(!Event::Update.mask() & $flags) });
+                    NVIC::unpend(Interrupt::$TIM);
+                }
+
+                /// Waits for `delay` periods of `1 / scale` seconds
+                fn wait(&mut self, delay: u32, scale: u64) {
+                    let ticks = u64::from(delay) * u64::from(self.clk.0);
+                    self.wait_ticks(ticks.div_ceil(scale));
+                }
+
+                /// Releases the TIM peripheral
+                pub fn release(self) -> $TIM {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit().opm().clear_bit());
+                    self.tim.dier.modify(|_, w| w.uie().clear_bit());
+                    self.tim
+                }
+            }
+
+            impl DelayUs<u32> for Delay<$TIM> {
+                fn delay_us(&mut self, us: u32) {
+                    self.wait(us, 1_000_000);
+                }
+            }
+
+            impl DelayUs<u16> for Delay<$TIM> {
+                fn delay_us(&mut self, us: u16) {
+                    self.wait(u32(us), 1_000_000);
+                }
+            }
+
+            impl DelayUs<u8> for Delay<$TIM> {
+                fn delay_us(&mut self, us: u8) {
+                    self.wait(u32(us), 1_000_000);
+                }
+            }
+
+            impl DelayMs<u32> for Delay<$TIM> {
+                fn delay_ms(&mut self, ms: u32) {
+                    self.wait(ms, 1_000);
+                }
+            }
+
+            impl DelayMs<u16> for Delay<$TIM> {
+                fn delay_ms(&mut self, ms: u16) {
+                    self.wait(u32(ms), 1_000);
+                }
+            }
+
+            impl DelayMs<u8> for Delay<$TIM> {
+                fn delay_ms(&mut self, ms: u8) {
+                    self.wait(u32(ms), 1_000);
+                }
+            }
+
+            impl<const FREQ: u32> fugit_timer::Delay<FREQ> for Delay<$TIM> {
+                type Error = Void;
+
+                fn delay(&mut self, duration: TimerDurationU32<FREQ>) -> Result<(), Void> {
+                    self.wait(duration.ticks(), u64::from(FREQ));
+                    Ok(())
+                }
+            }
+
+            impl DelayNs for Delay<$TIM> {
+                fn delay_ns(&mut self, ns: u32) {
+                    self.wait(ns, 1_000_000_000);
+                }
+
+                fn delay_us(&mut self, us: u32) {
+                    self.wait(us, 1_000_000);
+                }
+
+                fn delay_ms(&mut self, ms: u32) {
+                    self.wait(ms, 1_000);
+                }
+            }
         )+
     }
 }
 
+// The status flags of each timer: UIF, CCxIF, TIF and CCxOF as far as the
+// timer has them.
+const GP4_FLAGS: u32 = 0x1e5f;
+const GP2_FLAGS: u32 = 0x0647;
+const GP1_FLAGS: u32 = 0x0203;
+const BASIC_FLAGS: u32 = 0x0001;
+
 timers! {
-    TIM2: (tim2, tim2en, tim2rst, apb1enr, apb1rstr, apb1_tim_clk),
-    TIM3: (tim3, tim3en, tim3rst, apb1enr, apb1rstr, apb1_tim_clk),
-    TIM4: (tim4, tim4en, tim4rst, apb1enr, apb1rstr, apb1_tim_clk),
-    TIM5: (tim5, tim5en, tim5rst, apb1enr, apb1rstr, apb1_tim_clk),
-    TIM6: (tim6, tim6en, tim6rst, apb1enr, apb1rstr, apb1_tim_clk),
-    TIM7: (tim7, tim7en, tim7rst, apb1enr, apb1rstr, apb1_tim_clk),
-    TIM9: (tim9, tim9en, tim9rst, apb2enr, apb2rstr, apb2_tim_clk),
+    TIM2: (tim2, GP4_FLAGS),
+    TIM3: (tim3, GP4_FLAGS),
+    TIM4: (tim4, GP4_FLAGS),
+    TIM6: (tim6, BASIC_FLAGS),
+    TIM7: (tim7, BASIC_FLAGS),
+    TIM9: (tim9, GP2_FLAGS),
+    TIM10: (tim10, GP1_FLAGS),
+    TIM11: (tim11, GP1_FLAGS),
+}
+
+#[cfg(feature = "_cat3plus")]
+timers! {
+    TIM5: (tim5, GP4_FLAGS),
+}
+
+macro_rules! master_timers {
+    ($($TIM:ident: $Mode:ident,)+) => {
+        $(
+            impl Timer<$TIM> {
+                /// Selects the signal this timer drives on its trigger output (TRGO)
+                pub fn set_master_mode(&mut self, mode: $Mode) {
+                    #[allow(unused_unsafe)]
+                    self.tim.cr2.modify(|_, w| unsafe { w.mms().bits(mode as u8) });
+                }
+            }
+        )+
+    }
+}
+
+master_timers! {
+    TIM2: MasterMode,
+    TIM3: MasterMode,
+    TIM4: MasterMode,
+    TIM6: BasicMasterMode,
+    TIM7: BasicMasterMode,
+    TIM9: MasterMode,
+}
+
+#[cfg(feature = "_cat3plus")]
+master_timers! {
+    TIM5: MasterMode,
+}
+
+macro_rules! slave_timers {
+    ($($TIM:ident,)+) => {
+        $(
+            impl Timer<$TIM> {
+                /// Synchronizes this timer to the trigger output of `MASTER`
+                ///
+                /// ```no_run
+                /// // TIM4 restarts whenever TIM3 overflows
+                /// tim3.set_master_mode(MasterMode::Update);
+                /// tim4.set_slave_mode::<TIM3>(SlaveMode::Reset);
+                /// ```
+                pub fn set_slave_mode<MASTER>(&mut self, mode: SlaveMode)
+                where
+                    $TIM: InternalTrigger<MASTER>,
+                {
+                    let ts = <$TIM as InternalTrigger<MASTER>>::TS;
+                    self.tim
+                        .smcr
+                        .modify(|_, w| unsafe { w.ts().bits(ts).sms().bits(mode as u8) });
+                }
+
+                /// Disables slave mode, the counter is clocked by the internal clock again
+                pub fn disable_slave_mode(&mut self) {
+                    #[allow(unused_unsafe)]
+                    self.tim
+                        .smcr
+                        .modify(|_, w| unsafe { w.sms().bits(SlaveMode::Disabled as u8) });
+                }
+
+                /// Delays the effect of the trigger input so that this timer and its
+                /// slaves start in perfect sync (master/slave mode)
+                pub fn set_master_slave_mode(&mut self, enabled: bool) {
+                    self.tim.smcr.modify(|_, w| w.msm().bit(enabled));
+                }
+            }
+        )+
+    }
+}
+
+slave_timers! {
+    TIM2,
+    TIM3,
+    TIM4,
+    TIM9,
+}
+
+#[cfg(feature = "_cat3plus")]
+slave_timers! {
+    TIM5,
+}
+
+/// Extension trait that chains a master timer into a `SLAVE` timer
+pub trait ChainExt<MASTER, SLAVE> {
+    /// Chains this timer into `slave` as a 32-bit counter running at the `tick` rate
+    ///
+    /// The tick rate is rounded down to the closest rate the master prescaler can
+    /// produce. Fails with [`Error::InvalidTickRate`] if `tick` is 0 Hz.
+    fn chain<T>(self, slave: Timer<SLAVE>, tick: T) -> Result<ChainedCounter<MASTER, SLAVE>, Error>
+    where
+        T: Into<Hertz>;
+}
+
+/// Two 16-bit timers chained through an internal trigger into a 32-bit counter
+///
+/// The master counts timer clock ticks and the slave counts master overflows,
+/// so the pair behaves like a single free-running 32-bit counter.
+pub struct ChainedCounter<MASTER, SLAVE> {
+    master: Timer<MASTER>,
+    slave: Timer<SLAVE>,
+}
+
+macro_rules! chained_counters {
+    ($($SLAVE:ident: [$($MASTER:ident),+],)+) => {
+        $(
+            $(
+                impl ChainExt<$MASTER, $SLAVE> for Timer<$MASTER> {
+                    fn chain<T>(
+                        mut self,
+                        mut slave: Timer<$SLAVE>,
+                        tick: T,
+                    ) -> Result<ChainedCounter<$MASTER, $SLAVE>, Error>
+                    where
+                        T: Into<Hertz>,
+                    {
+                        let tick = tick.into().0;
+                        if tick == 0 {
+                            return Err(Error::InvalidTickRate);
+                        }
+                        let master = &mut self;
+                        master.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                        slave.tim.cr1.modify(|_, w| w.cen().clear_bit());
+
+                        let psc = (master.tim_clk().0 / tick).clamp(1, 1 << 16) - 1;
+                        master.tim.psc.write(|w| w.psc().bits(psc as u16));
+                        #[allow(unused_unsafe)]
+                        master.tim.arr.write(|w| unsafe { w.arr().bits(u16::MAX) });
+                        master.set_master_mode(MasterMode::Update);
+
+                        slave.tim.psc.write(|w| w.psc().bits(0));
+                        #[allow(unused_unsafe)]
+                        slave.tim.arr.write(|w| unsafe { w.arr().bits(u16::MAX) });
+                        slave.set_slave_mode::<$MASTER>(SlaveMode::ExternalClock);
+
+                        // load the prescalers, URS keeps the update flags clear
+                        master.tim.cr1.modify(|_, w| w.urs().set_bit());
+                        slave.tim.cr1.modify(|_, w| w.urs().set_bit());
+                        master.tim.egr.write(|w| w.ug().set_bit());
+                        slave.tim.egr.write(|w| w.ug().set_bit());
+                        master.tim.cnt.reset();
+                        slave.tim.cnt.reset();
+                        // the master UG still reaches the slave as a trigger
+                        slave.tim.sr.write(|w| unsafe { w.bits(0) });
+
+                        slave.tim.cr1.modify(|_, w| w.cen().set_bit());
+                        master.tim.cr1.modify(|_, w| w.cen().set_bit());
+
+                        Ok(ChainedCounter {
+                            master: self,
+                            slave,
+                        })
+                    }
+                }
+
+                impl ChainedCounter<$MASTER, $SLAVE> {
+                    /// Returns the 32-bit counter value
+                    pub fn count(&self) -> u32 {
+                        loop {
+                            let high = self.slave.tim.cnt.read().cnt().bits();
+                            let low = self.master.tim.cnt.read().cnt().bits();
+                            // retry if the master overflowed between the two reads
+                            if high == self.slave.tim.cnt.read().cnt().bits() {
+                                return u32(high) << 16 | u32(low);
+                            }
+                        }
+                    }
+
+                    /// Resets the 32-bit counter to zero
+                    pub fn reset(&mut self) {
+                        self.master.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                        self.master.tim.cnt.reset();
+                        self.slave.tim.cnt.reset();
+                        self.master.tim.cr1.modify(|_, w| w.cen().set_bit());
+                    }
+
+                    /// Unchains the timers and releases them
+                    pub fn release(mut self) -> (Timer<$MASTER>, Timer<$SLAVE>) {
+                        self.master.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                        self.slave.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                        self.master.set_master_mode(MasterMode::Reset);
+                        self.slave.disable_slave_mode();
+                        (self.master, self.slave)
+                    }
+                }
+            )+
+        )+
+    }
+}
+
+chained_counters! {
+    TIM2: [TIM3, TIM4],
+    TIM3: [TIM2, TIM4, TIM9],
+    TIM4: [TIM2, TIM3, TIM9],
+    TIM9: [TIM2, TIM3],
+}
+
+#[cfg(feature = "_cat3plus")]
+chained_counters! {
+    TIM2: [TIM5],
+    TIM3: [TIM5],
+    TIM5: [TIM2, TIM3, TIM4, TIM9],
 }
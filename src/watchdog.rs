@@ -1,13 +1,22 @@
-use crate::rcc::Rcc;
+use crate::rcc::{self, BusClock, Enable, Rcc};
 use crate::stm32::{IWDG, WWDG};
 use crate::time::Hertz;
 use hal::watchdog;
 
 pub struct IndependedWatchdog {
     iwdg: IWDG,
+    lsi: Hertz,
 }
 
 impl IndependedWatchdog {
+    /// Sets the LSI frequency the timeouts are computed from
+    ///
+    /// Pass `rcc.clocks.lsi_clk()` after [`Rcc::measure_lsi`](crate::rcc::Rcc::measure_lsi)
+    /// for accurate timeouts, the default is 38 kHz.
+    pub fn set_lsi_frequency(&mut self, lsi: Hertz) {
+        self.lsi = lsi;
+    }
+
     pub fn set_config(&mut self, pre: u8, reload: u16) {
         self.iwdg.kr.write(|w| w.key().reset());
         self.iwdg.kr.write(|w| w.key().enable());
@@ -36,10 +45,8 @@ impl watchdog::WatchdogEnable for IndependedWatchdog {
     where
         T: Into<Hertz>,
     {
-        const LSI_CLOCK: u32 = 38_000_u32;
-
         let freq = period.into().0;
-        let mut timeout = LSI_CLOCK / freq / 4;
+        let mut timeout = self.lsi.0 / freq / 4;
         let mut pre = 0;
         let mut reload = 0;
         while pre < 7 {
@@ -60,7 +67,10 @@ pub trait IndependedWatchdogExt {
 
 impl IndependedWatchdogExt for IWDG {
     fn watchdog(self) -> IndependedWatchdog {
-        IndependedWatchdog { iwdg: self }
+        IndependedWatchdog {
+            iwdg: self,
+            lsi: Hertz(38_000),
+        }
     }
 }
 
@@ -106,6 +116,11 @@ impl WindowWatchdog {
     pub fn listen(&mut self) {
         self.wwdg.cfr.modify(|_, w| w.ewi().set_bit());
     }
+
+    /// Sets whether the watchdog clock runs in sleep mode, see [`rcc::set_sleep_clock`]
+    pub fn set_sleep_clock(&mut self, enabled: bool) {
+        rcc::set_sleep_clock::<WWDG>(enabled);
+    }
 }
 
 impl watchdog::WatchdogEnable for WindowWatchdog {
@@ -128,10 +143,12 @@ pub trait WindowWatchdogExt {
 
 impl WindowWatchdogExt for WWDG {
     fn watchdog(self, rcc: &mut Rcc) -> WindowWatchdog {
-        rcc.rb.apb1enr.modify(|_, w| w.wwdgen().set_bit());
+        WWDG::enable(rcc);
+        // keep counting in sleep mode
+        rcc::set_sleep_clock::<WWDG>(true);
         WindowWatchdog {
             wwdg: self,
-            clk: rcc.clocks.apb1_clk().0 / 4096,
+            clk: WWDG::clock(&rcc.clocks).0 / 4096,
         }
     }
 }